@@ -1,7 +1,10 @@
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(
+    Deserialize, Serialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
 pub enum BodyType {
     #[serde(alias = "Crs")]
     Cars,
@@ -24,6 +27,7 @@ pub enum FuelType {
     Diesel,
     #[serde(alias = "GAS")]
     Gas,
+    #[allow(clippy::upper_case_acronyms)]
     #[serde(
         rename = "Battery electric",
         alias = "BATTERY ELECTRIC"
@@ -64,9 +68,18 @@ pub enum FuelType {
 #[derive(Deserialize, Debug, Clone, PartialEq)]
 pub enum LicenceStatus {
     Licensed,
+    #[allow(clippy::upper_case_acronyms)]
     SORN,
 }
 
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub enum KeeperType {
+    #[serde(alias = "PRIVATE")]
+    Private,
+    #[serde(alias = "COMPANY")]
+    Company,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum OptionalNumber {
@@ -82,139 +95,169 @@ pub enum FlagType {
     NotApplicable,
 }
 
-#[derive(Debug, Clone)]
+/// What identifies a vehicle in every make and model table, borrowed from a
+/// row.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct VehicleIdentity<'a> {
+    pub body_type: BodyType,
     pub make: &'a str,
     pub generic_model: &'a str,
     pub model: &'a str,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct Veh0120 {
-    #[serde(rename = "BodyType")]
-    pub body_type: BodyType,
-
-    #[serde(rename = "Make")]
-    pub make: String,
-
-    #[serde(rename = "GenModel")]
-    pub generic_model: String,
-
-    #[serde(rename = "Model")]
-    pub model: String,
-
-    #[serde(rename = "Fuel")]
-    pub fuel: FuelType,
-
-    #[serde(rename = "LicenceStatus")]
-    pub licence_status: LicenceStatus,
-
-    #[serde(flatten)]
-    pub extra: HashMap<String, i32>,
+/// Implemented by every table row, so that `Index::insert` can file it under
+/// the right make and model. Table rows should be declared with `table_row!`
+/// rather than implementing this by hand.
+pub trait HasIdentity {
+    fn identity(&self) -> VehicleIdentity<'_>;
 }
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct Veh0124 {
-    #[serde(rename = "BodyType")]
-    pub body_type: BodyType,
-
-    #[serde(rename = "Make")]
-    pub make: String,
-
-    #[serde(rename = "GenModel")]
-    pub generic_model: String,
-
-    #[serde(rename = "Model")]
-    pub model: String,
-
-    #[serde(rename = "YearFirstUsed")]
-    pub first_used: OptionalNumber,
-
-    #[serde(rename = "YearManufacture")]
-    pub manufactured: OptionalNumber,
-
-    #[serde(rename = "LicenceStatus")]
-    pub licence_status: LicenceStatus,
-
-    #[serde(flatten)]
-    pub extra: HashMap<String, OptionalNumber>,
+// Declares a table row: the identity columns that every make and model table
+// has, then the columns given, then the value columns in `extra`.
+//
+// The identity columns are fields of each row, rather than a flattened
+// struct, because serde reads flattened fields with `deserialize_any`, which
+// turns a model called "911" into a number.
+macro_rules! table_row {
+    (
+        $(#[$attr:meta])*
+        pub struct $row:ident {
+            $(
+                $(#[$field_attr:meta])*
+                pub $field:ident: $type:ty,
+            )*
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Deserialize, Debug, Clone)]
+        pub struct $row {
+            #[serde(rename = "BodyType")]
+            pub body_type: BodyType,
+
+            #[serde(rename = "Make")]
+            pub make: String,
+
+            #[serde(rename = "GenModel")]
+            pub generic_model: String,
+
+            #[serde(rename = "Model")]
+            pub model: String,
+
+            $(
+                $(#[$field_attr])*
+                pub $field: $type,
+            )*
+
+            #[serde(flatten)]
+            pub extra: HashMap<String, OptionalNumber>,
+        }
+
+        impl HasIdentity for $row {
+            fn identity(&self) -> VehicleIdentity<'_> {
+                VehicleIdentity {
+                    body_type: self.body_type,
+                    make: &self.make,
+                    generic_model: &self.generic_model,
+                    model: &self.model,
+                }
+            }
+        }
+    };
 }
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct Veh0160 {
-    #[serde(rename = "BodyType")]
-    pub body_type: BodyType,
-
-    #[serde(rename = "Make")]
-    pub make: String,
-
-    #[serde(rename = "GenModel")]
-    pub generic_model: String,
+table_row! {
+    #[allow(dead_code)]
+    pub struct Veh0120 {
+        #[serde(rename = "Fuel")]
+        pub fuel: FuelType,
 
-    #[serde(rename = "Model")]
-    pub model: String,
-
-    #[serde(rename = "Fuel")]
-    pub fuel: FuelType,
-
-    #[serde(flatten)]
-    pub extra: HashMap<String, i32>,
+        #[serde(rename = "LicenceStatus")]
+        pub licence_status: LicenceStatus,
+    }
 }
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct Veh0220 {
-    #[serde(rename = "BodyType")]
-    pub body_type: BodyType,
-
-    #[serde(rename = "Make")]
-    pub make: String,
-
-    #[serde(rename = "GenModel")]
-    pub generic_model: String,
-
-    #[serde(rename = "Model")]
-    pub model: String,
-
-    #[serde(rename = "Fuel")]
-    pub fuel: FuelType,
+table_row! {
+    pub struct Veh0124 {
+        #[serde(rename = "YearFirstUsed")]
+        pub first_used: OptionalNumber,
 
-    #[serde(rename = "EngineSizeSimple")]
-    pub engine_size_simple: OptionalNumber,
+        #[serde(rename = "YearManufacture")]
+        pub manufactured: OptionalNumber,
 
-    #[serde(rename = "EngineSizeDesc")]
-    pub engine_size_desc: String,
+        #[serde(rename = "LicenceStatus")]
+        pub licence_status: LicenceStatus,
+    }
+}
 
-    #[serde(rename = "LicenceStatus")]
-    pub licence_status: LicenceStatus,
+table_row! {
+    pub struct Veh0125 {
+        #[serde(rename = "Keepership")]
+        pub keeper_type: KeeperType,
+    }
+}
 
-    #[serde(flatten)]
-    pub extra: HashMap<String, i32>,
+table_row! {
+    /// New registrations by body colour.
+    pub struct Veh0150 {
+        #[serde(rename = "Colour")]
+        pub colour: String,
+    }
 }
 
-pub trait HasIdentity {
-    fn identity(&self) -> VehicleIdentity;
+table_row! {
+    #[allow(dead_code)]
+    pub struct Veh0160 {
+        #[serde(rename = "Fuel")]
+        pub fuel: FuelType,
+    }
 }
 
-impl HasIdentity for Veh0120 {
-    fn identity(&self) -> VehicleIdentity {
-        VehicleIdentity { make: &self.make, generic_model: &self.generic_model, model: &self.model }
+table_row! {
+    /// Licensed vehicles by region.
+    #[allow(dead_code)]
+    pub struct Veh0121 {
+        #[serde(rename = "ONS_Code")]
+        pub ons_code: String,
+
+        #[serde(rename = "ONS_Geography")]
+        pub region: String,
     }
 }
 
-impl HasIdentity for Veh0124 {
-    fn identity(&self) -> VehicleIdentity {
-        VehicleIdentity { make: &self.make, generic_model: &self.generic_model, model: &self.model }
+table_row! {
+    /// Licensed vehicles by local authority.
+    #[allow(dead_code)]
+    pub struct Veh0122 {
+        #[serde(rename = "ONS_Code")]
+        pub ons_code: String,
+
+        #[serde(rename = "ONS_Geography")]
+        pub local_authority: String,
     }
 }
 
-impl HasIdentity for Veh0160 {
-    fn identity(&self) -> VehicleIdentity {
-        VehicleIdentity { make: &self.make, generic_model: &self.generic_model, model: &self.model }
+table_row! {
+    /// First registrations by the vehicle's age when first registered in the
+    /// UK, which separates new vehicles from used imports.
+    pub struct Veh0170 {
+        #[serde(rename = "AgeAtFirstRegistration")]
+        pub age: String,
     }
 }
 
-impl HasIdentity for Veh0220 {
-    fn identity(&self) -> VehicleIdentity {
-        VehicleIdentity { make: &self.make, generic_model: &self.generic_model, model: &self.model }
+table_row! {
+    #[allow(dead_code)]
+    pub struct Veh0220 {
+        #[serde(rename = "Fuel")]
+        pub fuel: FuelType,
+
+        #[serde(rename = "EngineSizeSimple")]
+        pub engine_size_simple: OptionalNumber,
+
+        #[serde(rename = "EngineSizeDesc")]
+        pub engine_size_desc: String,
+
+        #[serde(rename = "LicenceStatus")]
+        pub licence_status: LicenceStatus,
     }
 }
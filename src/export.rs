@@ -0,0 +1,346 @@
+use crate::ids::Ids;
+use crate::keywords::SearchIndex;
+use crate::{Index, Link};
+use brotli::enc::BrotliEncoderParams;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Characters of a keyword or metaphone code that pick its search shard.
+const SHARD_PREFIX: usize = 2;
+
+// Brotli quality. The maximum, 11, takes more than twice as long for files
+// only 2% smaller.
+const BROTLI_QUALITY: i32 = 10;
+
+// Serialized files waiting for a writer thread.
+const QUEUE_LEN: usize = 64;
+
+/// Writes the index as a tree of static JSON files under `dir`, for hosting
+/// without SQLite:
+///
+/// - `makes.json`: the name and slug of every make.
+/// - `makes/<slug>.json`, `generic_models/<slug>.json` and
+///   `models/<slug>.json`: each entity's JSON, as in the database.
+/// - `search/<kind>/keywords/<prefix>.json`: the keywords starting with
+///   `prefix`, each with the slugs of the entities of that kind that have it.
+/// - `search/<kind>/metaphones/<prefix>.json`: the metaphone codes starting
+///   with `prefix`, each with the keywords that have it.
+///
+/// Every file has gzip (`.gz`) and brotli (`.br`) copies alongside, for
+/// servers that send precompressed files. The tree is written next to `dir`
+/// and swapped into place once it's complete: the old tree is moved aside to
+/// `<dir>.old` and only deleted once the new one is in place. The swap is two
+/// renames, so `dir` is briefly missing between them. If an export stops
+/// there, the next one moves the old tree back first.
+pub fn export(index: &Index, dir: &Path) -> Result<(), Box<dyn Error>> {
+    let temp = sibling(dir, ".tmp");
+    let old = sibling(dir, ".old");
+    if !dir.exists() && old.exists() {
+        fs::rename(&old, dir)?;
+    }
+    let _ = fs::remove_dir_all(&temp);
+    let _ = fs::remove_dir_all(&old);
+    if let Err(err) = write(index, &temp) {
+        let _ = fs::remove_dir_all(&temp);
+        return Err(err);
+    }
+    let replacing = dir.exists();
+    if replacing {
+        fs::rename(dir, &old)?;
+    }
+    if let Err(err) = fs::rename(&temp, dir) {
+        if replacing {
+            let _ = fs::rename(&old, dir);
+        }
+        return Err(err.into());
+    }
+    if replacing {
+        fs::remove_dir_all(&old)?;
+    }
+    Ok(())
+}
+
+// `path` with `suffix` added to its last component.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut sibling = path.as_os_str().to_owned();
+    sibling.push(suffix);
+    PathBuf::from(sibling)
+}
+
+// Serializes everything on this thread, and leaves the compressing, which
+// takes far longer, to one writer thread per CPU.
+fn write(index: &Index, dir: &Path) -> Result<(), Box<dyn Error>> {
+    let (files, queue) = mpsc::sync_channel::<(PathBuf, String)>(QUEUE_LEN);
+    let queue = Mutex::new(queue);
+    let error: Mutex<Option<String>> = Mutex::new(None);
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let result = thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let Ok((path, contents)) = queue.lock().unwrap().recv() else {
+                    break;
+                };
+                // After an error, the rest of the queue is drained unwritten,
+                // so that the serializing thread never blocks.
+                if error.lock().unwrap().is_some() {
+                    continue;
+                }
+                if let Err(err) = write_file(&path, &contents) {
+                    *error.lock().unwrap() = Some(format!("{}: {}", path.display(), err));
+                }
+            });
+        }
+        let result = write_all(index, dir, &files);
+        drop(files);
+        result
+    });
+    match error.into_inner().unwrap() {
+        Some(err) => Err(err.into()),
+        None => result,
+    }
+}
+
+fn write_all(
+    index: &Index,
+    dir: &Path,
+    files: &SyncSender<(PathBuf, String)>,
+) -> Result<(), Box<dyn Error>> {
+    let makes: Vec<Link> = index.makes.values().map(|m| m.link()).collect();
+    files.send((dir.join("makes.json"), serde_json::to_string(&makes)?))?;
+    for make in index.makes.values() {
+        let json = index.json("makes", &make.slug, make)?;
+        files.send((dir.join("makes").join(format!("{}.json", make.slug)), json))?;
+    }
+    for generic_model in index.generic_models.values() {
+        let json = index.json("generic_models", &generic_model.slug, generic_model)?;
+        let path = dir
+            .join("generic_models")
+            .join(format!("{}.json", generic_model.slug));
+        files.send((path, json))?;
+    }
+    for model in index.models.values() {
+        let json = index.json("models", &model.slug, model)?;
+        files.send((
+            dir.join("models").join(format!("{}.json", model.slug)),
+            json,
+        ))?;
+    }
+
+    // The shards list slugs, so the ids only need to hold within this export.
+    let make_id = Ids::default().assign(index.makes.keys());
+    let generic_model_id = Ids::default().assign(index.generic_models.keys());
+    let model_id = Ids::default().assign(index.models.keys());
+    let [make_search, generic_model_search, model_search] =
+        index.search_indexes(&make_id, &generic_model_id, &model_id, false);
+    let search = dir.join("search");
+    write_search(&search.join("makes"), &make_search, &make_id, files)?;
+    write_search(
+        &search.join("generic_models"),
+        &generic_model_search,
+        &generic_model_id,
+        files,
+    )?;
+    write_search(&search.join("models"), &model_search, &model_id, files)?;
+    Ok(())
+}
+
+// Writes a search index in shards, with each posting list as slugs.
+fn write_search(
+    dir: &Path,
+    search: &SearchIndex,
+    ids: &BTreeMap<Arc<str>, u32>,
+    files: &SyncSender<(PathBuf, String)>,
+) -> Result<(), Box<dyn Error>> {
+    let slugs: BTreeMap<u32, &str> = ids.iter().map(|(slug, id)| (*id, &**slug)).collect();
+    let mut shards: BTreeMap<String, BTreeMap<&str, Vec<&str>>> = BTreeMap::new();
+    for (keyword, ids) in search.keywords() {
+        let slugs = ids.iter().map(|id| slugs[id]).collect();
+        shards
+            .entry(shard(keyword))
+            .or_default()
+            .insert(keyword, slugs);
+    }
+    for (prefix, shard) in shards {
+        let path = dir.join("keywords").join(format!("{}.json", prefix));
+        files.send((path, serde_json::to_string(&shard)?))?;
+    }
+
+    let mut shards: BTreeMap<String, BTreeMap<&str, Vec<&str>>> = BTreeMap::new();
+    for (metaphone, words) in search.metaphones() {
+        let words = words.iter().map(String::as_str).collect();
+        shards
+            .entry(shard(metaphone))
+            .or_default()
+            .insert(metaphone, words);
+    }
+    for (prefix, shard) in shards {
+        let path = dir.join("metaphones").join(format!("{}.json", prefix));
+        files.send((path, serde_json::to_string(&shard)?))?;
+    }
+    Ok(())
+}
+
+// The shard for a keyword or metaphone code. Neither ever starts with an
+// underscore, so that's safe to use for the empty code.
+fn shard(key: &str) -> String {
+    if key.is_empty() {
+        "_".to_string()
+    } else {
+        key.chars().take(SHARD_PREFIX).collect()
+    }
+}
+
+// Writes a file, and its gzip and brotli copies.
+fn write_file(path: &Path, contents: &str) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, contents)?;
+
+    let mut gz = path.as_os_str().to_owned();
+    gz.push(".gz");
+    let mut encoder = GzEncoder::new(File::create(gz)?, Compression::best());
+    encoder.write_all(contents.as_bytes())?;
+    encoder.finish()?;
+
+    let mut br = path.as_os_str().to_owned();
+    br.push(".br");
+    let params = BrotliEncoderParams {
+        quality: BROTLI_QUALITY,
+        ..Default::default()
+    };
+    brotli::BrotliCompress(&mut contents.as_bytes(), &mut File::create(br)?, &params)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema;
+    use crate::testing::{build, write_inputs, TempDir};
+    use flate2::read::GzDecoder;
+    use rusqlite::Connection;
+    use serde_json::{json, Value};
+    use std::io::Read;
+
+    fn files(dir: &Path) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                files.extend(self::files(&path));
+            } else {
+                files.push(path);
+            }
+        }
+        files.sort();
+        files
+    }
+
+    fn read_json(path: &Path) -> Value {
+        serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn exports_entities_and_search_shards() {
+        let dir = TempDir::new();
+        let inputs = dir.join("csv");
+        write_inputs(&inputs);
+        let database = dir.join("db.sqlite3");
+        let index = build(&inputs, &database, None, false, schema::CURRENT_VERSION);
+        let site = dir.join("site");
+        export(&index, &site).unwrap();
+
+        assert_eq!(
+            read_json(&site.join("makes.json")),
+            json!([
+                { "slug": "ford", "name": "FORD" },
+                { "slug": "porsche", "name": "PORSCHE" },
+            ])
+        );
+        let db = Connection::open(&database).unwrap();
+        for (kind, slug) in [
+            ("makes", "ford"),
+            ("generic_models", "ford_ka"),
+            ("models", "ford_ka_1.3"),
+        ] {
+            let json: String = db
+                .query_row(
+                    &format!("SELECT json FROM {kind} WHERE slug = ?1"),
+                    [slug],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            let path = site.join(kind).join(format!("{slug}.json"));
+            assert_eq!(fs::read_to_string(path).unwrap(), json);
+        }
+
+        let search = site.join("search");
+        assert_eq!(
+            read_json(&search.join("makes/keywords/fo.json")),
+            json!({ "ford": ["ford"] })
+        );
+        assert_eq!(
+            read_json(&search.join("models/keywords/ka.json")),
+            json!({ "ka": ["ford_ka_1.3"] })
+        );
+        let metaphones = read_json(&search.join("models/metaphones/KR.json"));
+        assert!(metaphones
+            .as_object()
+            .unwrap()
+            .iter()
+            .any(|(code, words)| code.starts_with("KR")
+                && words.as_array().unwrap().contains(&json!("cortina"))));
+
+        let files = files(&site);
+        let plain: Vec<&PathBuf> = files
+            .iter()
+            .filter(|f| !matches!(f.extension().and_then(|e| e.to_str()), Some("gz" | "br")))
+            .collect();
+        assert_eq!(files.len(), plain.len() * 3);
+        for path in plain {
+            let contents = fs::read(path).unwrap();
+
+            let mut gz = Vec::new();
+            GzDecoder::new(File::open(sibling(path, ".gz")).unwrap())
+                .read_to_end(&mut gz)
+                .unwrap();
+            assert_eq!(gz, contents, "{}", path.display());
+
+            let mut br = Vec::new();
+            brotli::BrotliDecompress(&mut File::open(sibling(path, ".br")).unwrap(), &mut br)
+                .unwrap();
+            assert_eq!(br, contents, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn replaces_the_previous_export() {
+        let dir = TempDir::new();
+        let inputs = dir.join("csv");
+        write_inputs(&inputs);
+        let database = dir.join("db.sqlite3");
+        let index = build(&inputs, &database, None, false, schema::CURRENT_VERSION);
+        let site = dir.join("site");
+        export(&index, &site).unwrap();
+        fs::write(site.join("stale.json"), "{}").unwrap();
+
+        export(&index, &site).unwrap();
+        assert!(site.join("makes.json").exists());
+        assert!(!site.join("stale.json").exists());
+        assert!(!sibling(&site, ".tmp").exists());
+        assert!(!sibling(&site, ".old").exists());
+
+        // An export that stopped after moving the old tree aside.
+        fs::rename(&site, sibling(&site, ".old")).unwrap();
+        export(&index, &site).unwrap();
+        assert!(site.join("makes.json").exists());
+        assert!(!sibling(&site, ".old").exists());
+    }
+}
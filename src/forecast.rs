@@ -0,0 +1,175 @@
+use crate::period::Quarter;
+use crate::Stats;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Fewest quarterly points after the peak that we'll fit a curve to.
+const MIN_POINTS: usize = 8;
+
+// Projections further out than this many years are reported as "no
+// projection" rather than as a meaningless far-future date.
+const HORIZON_YEARS: f64 = 100.0;
+
+// Two-sided 95% interval on the fitted decay rate.
+const Z_95: f64 = 1.96;
+
+/// Projected year in which the last vehicle of a model leaves the road.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Forecast {
+    pub year: i32,
+    pub earliest: i32,
+    /// `null` if, at the slow end of the band, the decline might stop.
+    pub latest: Option<i32>,
+}
+
+/// Fits a forecast to the total licensed and SORN count in each quarter.
+pub fn forecast_stats(stats: &Stats) -> Option<Forecast> {
+    let mut totals: BTreeMap<Quarter, i32> = stats.quarterly_licensed.counts().collect();
+    for (quarter, count) in stats.quarterly_sorn.counts() {
+        *totals.entry(quarter).or_insert(0) += count;
+    }
+    let series: Vec<(f64, f64)> = totals
+        .iter()
+        .map(|(q, n)| (q.end_as_year(), *n as f64))
+        .collect();
+    forecast(&series)
+}
+
+/// Fits an exponential decay curve to `(year, count)` points from the peak
+/// onwards, and projects the year in which the expected count drops below
+/// one half. The band comes from the 95% interval on the decay rate.
+///
+/// Returns `None` if there are too few points, the series isn't declining, or
+/// the projection is beyond the horizon.
+pub fn forecast(series: &[(f64, f64)]) -> Option<Forecast> {
+    let peak = series
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.1.total_cmp(&b.1))?
+        .0;
+    let points: Vec<(f64, f64)> = series[peak..]
+        .iter()
+        .filter(|(_, n)| *n > 0.0)
+        .map(|(t, n)| (*t, n.ln()))
+        .collect();
+    if points.len() < MIN_POINTS {
+        return None;
+    }
+
+    // Ordinary least squares fit of ln(count) against time.
+    let n = points.len() as f64;
+    let t_mean = points.iter().map(|(t, _)| t).sum::<f64>() / n;
+    let y_mean = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let sxx: f64 = points.iter().map(|(t, _)| (t - t_mean).powi(2)).sum();
+    let sxy: f64 = points
+        .iter()
+        .map(|(t, y)| (t - t_mean) * (y - y_mean))
+        .sum();
+    if sxx <= 0.0 {
+        return None;
+    }
+    let slope = sxy / sxx;
+    let sse: f64 = points
+        .iter()
+        .map(|(t, y)| (y - y_mean - slope * (t - t_mean)).powi(2))
+        .sum();
+    let slope_se = (sse / (n - 2.0) / sxx).sqrt();
+
+    let last = points.last()?.0;
+    let zero_at = |rate: f64| -> Option<i32> {
+        if rate >= 0.0 {
+            return None;
+        }
+        let t = t_mean + (0.5f64.ln() - y_mean) / rate;
+        if t - last > HORIZON_YEARS {
+            None
+        } else {
+            Some(t.max(last).floor() as i32)
+        }
+    };
+
+    Some(Forecast {
+        year: zero_at(slope)?,
+        earliest: zero_at(slope - Z_95 * slope_se)?,
+        latest: zero_at(slope + Z_95 * slope_se),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quarterly(start: f64, counts: impl Iterator<Item = f64>) -> Vec<(f64, f64)> {
+        counts
+            .enumerate()
+            .map(|(i, n)| (start + i as f64 / 4.0, n))
+            .collect()
+    }
+
+    #[test]
+    fn exact_decay() {
+        // 1000 * 0.8^t drops below 0.5 after 34.06 years.
+        let series = quarterly(
+            2010.0,
+            (0..40).map(|i| 1000.0 * 0.8f64.powf(i as f64 / 4.0)),
+        );
+        assert_eq!(
+            forecast(&series),
+            Some(Forecast {
+                year: 2044,
+                earliest: 2044,
+                latest: Some(2044),
+            })
+        );
+    }
+
+    #[test]
+    fn fits_from_peak() {
+        // Growth for the first five years shouldn't affect the projection.
+        let growth = (0..20).map(|i| 100.0 * (i + 1) as f64);
+        let decay = (0..40).map(|i| 2000.0 * 0.8f64.powf(i as f64 / 4.0));
+        let series = quarterly(2005.0, growth.chain(decay));
+        let forecast = forecast(&series).unwrap();
+        // 2000 * 0.8^t drops below 0.5 after 37.17 years, from 2010.
+        assert_eq!(forecast.year, 2047);
+    }
+
+    #[test]
+    fn noisy_decay_has_band() {
+        let series = quarterly(
+            2010.0,
+            (0..40).map(|i| {
+                let noise = if i % 2 == 0 { 1.1 } else { 0.9 };
+                5000.0 * 0.9f64.powf(i as f64 / 4.0) * noise
+            }),
+        );
+        let forecast = forecast(&series).unwrap();
+        assert!(forecast.earliest < forecast.year);
+        assert!(forecast.latest.unwrap() > forecast.year);
+    }
+
+    #[test]
+    fn flat_series_has_no_projection() {
+        let series = quarterly(2010.0, (0..40).map(|_| 1000.0));
+        assert_eq!(forecast(&series), None);
+    }
+
+    #[test]
+    fn growing_series_has_no_projection() {
+        let series = quarterly(2010.0, (0..40).map(|i| 1000.0 + i as f64));
+        assert_eq!(forecast(&series), None);
+    }
+
+    #[test]
+    fn too_few_points() {
+        let series = quarterly(2010.0, (0..5).map(|i| 1000.0 * 0.5f64.powi(i)));
+        assert_eq!(forecast(&series), None);
+    }
+
+    #[test]
+    fn slow_decay_beyond_horizon() {
+        let series = quarterly(2010.0, (0..40).map(|i| 1e6 * 0.99f64.powf(i as f64 / 4.0)));
+        assert_eq!(forecast(&series), None);
+    }
+}
@@ -0,0 +1,150 @@
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Arc;
+
+/// Allocates the ids for one entity table, carrying over the ids that slugs
+/// had in a previous build so that bookmarks and cached posting lists stay
+/// valid. Ids are never reused, even once their slug disappears.
+pub struct Ids {
+    ids: BTreeMap<String, u32>,
+    next_id: u32,
+}
+
+impl Default for Ids {
+    /// Ids for a build with nothing before it.
+    fn default() -> Ids {
+        Ids {
+            ids: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+}
+
+impl Ids {
+    /// Loads the ids for `table` from a previous database, if it has them.
+    pub fn load(previous: Option<&Connection>, table: &str) -> Result<Ids, Box<dyn Error>> {
+        let mut ids = BTreeMap::new();
+        let mut next_id = 1;
+        if let Some(db) = previous {
+            let has_ids: bool = db.query_row(
+                "SELECT COUNT(1) > 0 FROM pragma_table_info(?1) WHERE name = 'id'",
+                [table],
+                |row| row.get(0),
+            )?;
+            if has_ids {
+                let mut stmt = db.prepare(&format!("SELECT slug, id FROM {table}"))?;
+                for row in stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))? {
+                    let (slug, id): (String, u32) = row?;
+                    next_id = next_id.max(id + 1);
+                    ids.insert(slug, id);
+                }
+            }
+            let has_sequences: bool = db.query_row(
+                "SELECT COUNT(1) > 0 FROM sqlite_master WHERE name = 'id_sequences'",
+                [],
+                |row| row.get(0),
+            )?;
+            if has_sequences {
+                let saved: Option<u32> = db
+                    .query_row(
+                        "SELECT next_id FROM id_sequences WHERE name = ?1",
+                        [table],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                next_id = next_id.max(saved.unwrap_or(1));
+            }
+        }
+        Ok(Ids { ids, next_id })
+    }
+
+    /// Returns the ids for `slugs`, allocating new ones in slug order.
+    pub fn assign<'a>(
+        &mut self,
+        slugs: impl Iterator<Item = &'a Arc<str>>,
+    ) -> BTreeMap<Arc<str>, u32> {
+        let mut ret = BTreeMap::new();
+        for slug in slugs {
+            let id = *self.ids.entry(slug.to_string()).or_insert_with(|| {
+                self.next_id += 1;
+                self.next_id - 1
+            });
+            ret.insert(slug.clone(), id);
+        }
+        ret
+    }
+
+    pub fn next_id(&self) -> u32 {
+        self.next_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema;
+
+    fn slugs(slugs: &[&str]) -> Vec<Arc<str>> {
+        slugs.iter().map(|s| Arc::from(*s)).collect()
+    }
+
+    // A previous database with the given model ids, and `next_id` saved.
+    fn previous(version: u32, ids: &[(&str, u32)], next_id: u32) -> Connection {
+        let db = Connection::open_in_memory().unwrap();
+        schema::create(&db, version).unwrap();
+        for (slug, id) in ids {
+            db.execute("INSERT INTO models VALUES (?1, ?2, '{}')", (slug, id))
+                .unwrap();
+        }
+        if version >= 2 {
+            db.execute("INSERT INTO id_sequences VALUES ('models', ?1)", [next_id])
+                .unwrap();
+        }
+        db
+    }
+
+    #[test]
+    fn first_build_numbers_from_one() {
+        let mut ids = Ids::load(None, "models").unwrap();
+        let assigned = ids.assign(slugs(&["audi_a4", "bmw_m3", "ford_ka"]).iter());
+        assert_eq!(assigned[&Arc::from("audi_a4")], 1);
+        assert_eq!(assigned[&Arc::from("bmw_m3")], 2);
+        assert_eq!(assigned[&Arc::from("ford_ka")], 3);
+        assert_eq!(ids.next_id(), 4);
+    }
+
+    #[test]
+    fn ids_survive_a_reload() {
+        let db = previous(2, &[("audi_a4", 1), ("ford_ka", 2)], 3);
+        let mut ids = Ids::load(Some(&db), "models").unwrap();
+        let assigned = ids.assign(slugs(&["audi_a4", "bmw_m3", "ford_ka"]).iter());
+        assert_eq!(assigned[&Arc::from("audi_a4")], 1);
+        assert_eq!(assigned[&Arc::from("ford_ka")], 2);
+        assert_eq!(assigned[&Arc::from("bmw_m3")], 3);
+    }
+
+    #[test]
+    fn ids_of_removed_slugs_are_not_reused() {
+        // ford_puma had id 3 in an earlier build, and has since gone.
+        let db = previous(2, &[("audi_a4", 1), ("ford_ka", 2)], 4);
+        let mut ids = Ids::load(Some(&db), "models").unwrap();
+        let assigned = ids.assign(slugs(&["audi_a4", "ford_ka", "ford_puma"]).iter());
+        assert_eq!(assigned[&Arc::from("ford_puma")], 4);
+        assert_eq!(ids.next_id(), 5);
+    }
+
+    #[test]
+    fn version_1_carries_over_model_ids() {
+        let db = previous(1, &[("audi_a4", 7)], 0);
+        let mut ids = Ids::load(Some(&db), "models").unwrap();
+        let assigned = ids.assign(slugs(&["audi_a4", "bmw_m3"]).iter());
+        assert_eq!(assigned[&Arc::from("audi_a4")], 7);
+        assert_eq!(assigned[&Arc::from("bmw_m3")], 8);
+
+        // Makes had no ids in version 1.
+        let mut ids = Ids::load(Some(&db), "makes").unwrap();
+        let assigned = ids.assign(slugs(&["audi"]).iter());
+        assert_eq!(assigned[&Arc::from("audi")], 1);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::tables::{Input, InputFile, Table};
+use crate::{GenericModel, Index, Make, Model, Stats};
+use rusqlite::Connection;
+use std::collections::BTreeSet;
+use std::error::Error;
+
+/// The sources of every table whose input files, or their contents, differ
+/// from the previous build's, or that had a file skipped last time.
+pub fn changed_sources<'a>(
+    tables: &'a [Box<dyn Table>],
+    inputs: &[Input],
+    previous: &[InputFile],
+) -> BTreeSet<&'a str> {
+    tables
+        .iter()
+        .filter(|table| {
+            let now: BTreeSet<_> = inputs
+                .iter()
+                .map(|input| &input.file)
+                .filter(|file| file.table == table.name())
+                .map(InputFile::key)
+                .collect();
+            let before: BTreeSet<_> = previous
+                .iter()
+                .filter(|file| file.table == table.name() && file.skipped.is_none())
+                .map(InputFile::key)
+                .collect();
+            now != before
+        })
+        .map(|table| table.source())
+        .collect()
+}
+
+/// Loads the entities saved by a previous build, ready for the tables from
+/// `sources` to be loaded again on top.
+///
+/// Models keep their stats apart from the fields merged from `sources`, and
+/// generic model and make stats are left to be rolled up again. Models left
+/// with no stats are dropped, along with generic models and makes left with
+/// no models; any that are still in the changed tables come back when they're
+/// loaded. Every row adds a cell to its model's stats, even if it's zero or a
+/// flag, so a model is only left with no stats if none of the other tables
+/// has a row for it, and the result is the same as a full build. (The GB
+/// tables only add quarters before `UK_CUTOVER`, which the published files
+/// all have columns for.)
+pub fn load_index(
+    db: &Connection,
+    tables: &[Box<dyn Table>],
+    sources: &BTreeSet<&str>,
+) -> Result<Index, Box<dyn Error>> {
+    let mut index = Index::new();
+    for_each_json(db, "models", |json| {
+        let mut model: Model = serde_json::from_str(json)?;
+        for table in tables.iter().filter(|t| sources.contains(t.source())) {
+            table.clear(&mut model.stats);
+        }
+        if !model.stats.is_empty() {
+            index.models.insert(model.slug.clone(), model);
+        }
+        Ok(())
+    })?;
+    for_each_json(db, "generic_models", |json| {
+        let mut generic_model: GenericModel = serde_json::from_str(json)?;
+        generic_model.stats = Stats::new();
+        generic_model
+            .models
+            .retain(|m| index.models.contains_key(&m.slug));
+        if !generic_model.models.is_empty() {
+            index
+                .generic_models
+                .insert(generic_model.slug.clone(), generic_model);
+        }
+        Ok(())
+    })?;
+    for_each_json(db, "makes", |json| {
+        let mut make: Make = serde_json::from_str(json)?;
+        make.stats = Stats::new();
+        make.generic_models
+            .retain(|g| index.generic_models.contains_key(&g.slug));
+        if !make.generic_models.is_empty() {
+            index.makes.insert(make.slug.clone(), make);
+        }
+        Ok(())
+    })?;
+    Ok(index)
+}
+
+fn for_each_json(
+    db: &Connection,
+    table: &str,
+    mut f: impl FnMut(&str) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut stmt = db.prepare(&format!("SELECT json FROM {table}"))?;
+    for json in stmt.query_map([], |row| row.get::<_, String>(0))? {
+        f(&json?)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use crate::dft::{BodyType, VehicleIdentity};
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// Hands out shared copies of strings, so that a name or slug that appears
+/// in many places (map keys, links, the entity itself) is only allocated
+/// once.
+#[derive(Debug, Default)]
+pub struct Interner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(interned) = self.strings.get(s) {
+            return interned.clone();
+        }
+        let interned: Arc<str> = Arc::from(s);
+        self.strings.insert(interned.clone());
+        interned
+    }
+}
+
+/// A row's identity columns, interned, as the key of the cache that takes
+/// rows straight to their model. The cache is searched with the borrowed
+/// `VehicleIdentity` of each row, through `IdentityKey`, so looking up a
+/// vehicle that's already been seen copies nothing.
+#[derive(Debug)]
+pub struct InternedIdentity {
+    body_type: BodyType,
+    make: Arc<str>,
+    generic_model: Arc<str>,
+    model: Arc<str>,
+}
+
+impl InternedIdentity {
+    pub fn new(identity: &VehicleIdentity, strings: &mut Interner) -> InternedIdentity {
+        InternedIdentity {
+            body_type: identity.body_type,
+            make: strings.intern(identity.make),
+            generic_model: strings.intern(identity.generic_model),
+            model: strings.intern(identity.model),
+        }
+    }
+}
+
+/// An identity, however it's stored.
+pub trait IdentityKey {
+    fn key(&self) -> VehicleIdentity<'_>;
+}
+
+impl IdentityKey for InternedIdentity {
+    fn key(&self) -> VehicleIdentity<'_> {
+        VehicleIdentity {
+            body_type: self.body_type,
+            make: &self.make,
+            generic_model: &self.generic_model,
+            model: &self.model,
+        }
+    }
+}
+
+impl IdentityKey for VehicleIdentity<'_> {
+    fn key(&self) -> VehicleIdentity<'_> {
+        self.clone()
+    }
+}
+
+impl<'a> Borrow<dyn IdentityKey + 'a> for InternedIdentity {
+    fn borrow(&self) -> &(dyn IdentityKey + 'a) {
+        self
+    }
+}
+
+impl Hash for dyn IdentityKey + '_ {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+impl PartialEq for dyn IdentityKey + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for dyn IdentityKey + '_ {}
+
+// The same hash and equality as the borrowed form, as `HashMap` requires.
+impl Hash for InternedIdentity {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+impl PartialEq for InternedIdentity {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for InternedIdentity {}
@@ -0,0 +1,251 @@
+use crate::{GenericModel, Make, Model};
+use schemars::generate::SchemaSettings;
+use schemars::{json_schema, Schema};
+use serde_json::{Map, Value};
+
+/// JSON Schema for the entity JSON in the `json` columns: `Make`,
+/// `GenericModel` and `Model`, with the stats flattened into each. Every type
+/// they use is under `$defs`, so a reader can refer to a single entity type as
+/// e.g. `#/$defs/Model`.
+pub fn schema() -> Schema {
+    let mut generator = SchemaSettings::draft2020_12()
+        .for_serialize()
+        .into_generator();
+    let entities = [
+        generator.subschema_for::<Make>(),
+        generator.subschema_for::<GenericModel>(),
+        generator.subschema_for::<Model>(),
+    ];
+    let defs = generator.take_definitions(true);
+    json_schema!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "howmanyleft entities",
+        "anyOf": entities,
+        "$defs": defs,
+    })
+}
+
+/// TypeScript definitions for every type in `schema`'s `$defs`.
+pub fn typescript(schema: &Schema) -> String {
+    let mut ts = String::from("// Generated by `ingest --typescript`. Don't edit by hand.\n");
+    let defs = schema.get("$defs").and_then(Value::as_object);
+    for (name, def) in defs.into_iter().flatten() {
+        ts.push('\n');
+        ts.push_str(&doc_comment(def, ""));
+        ts.push_str(&format!("export type {} = {};\n", name, ts_type(def, "")));
+    }
+    ts
+}
+
+fn doc_comment(schema: &Value, indent: &str) -> String {
+    match schema.get("description").and_then(Value::as_str) {
+        Some(description) => format!("{indent}/** {} */\n", description.replace('\n', " ")),
+        None => String::new(),
+    }
+}
+
+// The TypeScript type for a schema. Objects with properties go over several
+// lines, indented from `indent`.
+fn ts_type(schema: &Value, indent: &str) -> String {
+    let Some(schema) = schema.as_object() else {
+        // `true` accepts anything.
+        return "unknown".to_string();
+    };
+    if let Some(r) = schema.get("$ref").and_then(Value::as_str) {
+        return r.rsplit('/').next().unwrap().to_string();
+    }
+    if let Some(options) = schema.get("anyOf").or(schema.get("oneOf")) {
+        return union(options.as_array().into_iter().flatten(), indent);
+    }
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        return values
+            .iter()
+            .map(Value::to_string)
+            .collect::<Vec<_>>()
+            .join(" | ");
+    }
+    if let Some(value) = schema.get("const") {
+        return value.to_string();
+    }
+    match schema.get("type") {
+        Some(Value::Array(types)) => {
+            let types: Vec<String> = types
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|t| simple_type(t, schema, indent))
+                .collect();
+            types.join(" | ")
+        }
+        Some(Value::String(t)) => simple_type(t, schema, indent),
+        _ => "unknown".to_string(),
+    }
+}
+
+fn union<'a>(options: impl Iterator<Item = &'a Value>, indent: &str) -> String {
+    let options: Vec<String> = options.map(|o| ts_type(o, indent)).collect();
+    options.join(" | ")
+}
+
+fn simple_type(t: &str, schema: &Map<String, Value>, indent: &str) -> String {
+    match t {
+        "string" => "string".to_string(),
+        "integer" | "number" => "number".to_string(),
+        "boolean" => "boolean".to_string(),
+        "null" => "null".to_string(),
+        "array" => match schema.get("items") {
+            Some(items) => {
+                let items = ts_type(items, indent);
+                if items.contains(' ') {
+                    format!("({})[]", items)
+                } else {
+                    format!("{}[]", items)
+                }
+            }
+            None => "unknown[]".to_string(),
+        },
+        "object" => object_type(schema, indent),
+        _ => "unknown".to_string(),
+    }
+}
+
+// An object's properties, plus an index signature for any other keys that it
+// allows, e.g. the quarters of a series.
+fn object_type(schema: &Map<String, Value>, indent: &str) -> String {
+    let mut parts = Vec::new();
+    if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+        let required: Vec<&str> = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect();
+        let inner = format!("{indent}  ");
+        let mut fields = String::from("{\n");
+        for (name, property) in properties {
+            fields.push_str(&doc_comment(property, &inner));
+            let optional = if required.contains(&name.as_str()) {
+                ""
+            } else {
+                "?"
+            };
+            fields.push_str(&format!(
+                "{inner}{name}{optional}: {};\n",
+                ts_type(property, &inner)
+            ));
+        }
+        fields.push_str(indent);
+        fields.push('}');
+        parts.push(fields);
+    }
+    let mut values: Vec<&Value> = schema
+        .get("patternProperties")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flat_map(|p| p.values())
+        .collect();
+    match schema.get("additionalProperties") {
+        Some(Value::Bool(false)) => {}
+        Some(value) => values.push(value),
+        None => {}
+    }
+    if !values.is_empty() {
+        let values = union(values.into_iter(), indent);
+        parts.push(format!("{{ [key: string]: {} }}", values));
+    }
+    if parts.is_empty() {
+        return "{ [key: string]: unknown }".to_string();
+    }
+    parts.join(" & ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{build, write_inputs, TempDir};
+    use crate::{dft, schema, Index};
+    use jsonschema::Validator;
+    use rusqlite::Connection;
+    use serde_json::json;
+
+    // A validator for one of the entity types under `$defs`.
+    fn validator(def: &str) -> Validator {
+        let schema = schema().to_value();
+        jsonschema::validator_for(&json!({
+            "$ref": format!("#/$defs/{def}"),
+            "$defs": schema["$defs"],
+        }))
+        .unwrap()
+    }
+
+    // Where `value` doesn't match, as JSON pointers.
+    fn errors(validator: &Validator, value: &Value) -> Vec<String> {
+        let mut errors: Vec<String> = validator
+            .iter_errors(value)
+            .map(|e| e.instance_path().to_string())
+            .collect();
+        errors.sort();
+        errors
+    }
+
+    fn entities(kind: &str, db: &Connection) -> Vec<Value> {
+        let mut stmt = db.prepare(&format!("SELECT json FROM {kind}")).unwrap();
+        stmt.query_map([], |row| row.get::<_, String>(0))
+            .unwrap()
+            .map(|json| serde_json::from_str(&json.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn entities_match_the_schema() {
+        let dir = TempDir::new();
+        let inputs = dir.join("csv");
+        write_inputs(&inputs);
+        let database = dir.join("db.sqlite3");
+        build(&inputs, &database, None, false, schema::CURRENT_VERSION);
+        let db = Connection::open(&database).unwrap();
+
+        let any = jsonschema::validator_for(&schema().to_value()).unwrap();
+        for (kind, def) in [
+            ("makes", "Make"),
+            ("generic_models", "GenericModel"),
+            ("models", "Model"),
+        ] {
+            let validator = validator(def);
+            let entities = entities(kind, &db);
+            assert!(!entities.is_empty());
+            for entity in entities {
+                let errors = errors(&validator, &entity);
+                assert!(errors.is_empty(), "{}: {:#?}", entity["slug"], errors);
+                assert!(any.is_valid(&entity));
+            }
+        }
+    }
+
+    #[test]
+    fn validate_finds_mismatches() {
+        let validator = validator("Model");
+        assert!(!validator.is_valid(&json!({ "name": "KA 1.3" })));
+
+        let mut index = Index::new();
+        let slug = index
+            .add(&dft::VehicleIdentity {
+                body_type: dft::BodyType::Cars,
+                make: "FORD",
+                generic_model: "KA",
+                model: "KA 1.3",
+            })
+            .unwrap();
+        let mut entity = serde_json::to_value(&index.models[&slug]).unwrap();
+        assert!(validator.is_valid(&entity));
+
+        // A key that isn't a quarter is reported against the series, as no
+        // other keys are allowed.
+        entity["quarterly_licensed"]["2015Q1"] = json!(1);
+        entity["quarterly_sorn"]["2015 q1"] = json!("[y]");
+        assert_eq!(
+            errors(&validator, &entity),
+            ["/quarterly_licensed", "/quarterly_sorn/2015 q1"]
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use nlp::phonetics::metaphone::double_metaphone::double_metaphone;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rusqlite::Connection;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::error::Error;
+
+// Runs of letters or digits within a single word, e.g. "320d" -> "320", "d".
+static FRAGMENT_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"[a-z]+|[0-9]+").unwrap());
+
+// Tokens that appear in so many model names that their posting lists are
+// useless for search.
+static STOP_WORDS: &[&str] = &["a", "d", "e", "i", "s", "the", "v", "x"];
+
+fn is_stop_word(token: &str) -> bool {
+    STOP_WORDS.contains(&token)
+}
+
+/// Splits a vehicle name into search tokens.
+///
+/// Each whitespace-separated word is indexed in its joined form, with any
+/// punctuation removed ("320d", "mx5", "16v"). Words that mix letters, digits
+/// or hyphens are also indexed by their fragments ("rolls-royce" -> "rolls",
+/// "royce"), but single-character fragments are dropped so that "320d" doesn't
+/// land in the posting list for "d".
+///
+/// Stop words are not removed here; see `remove_stop_words`.
+pub fn tokenise(name: &str) -> HashSet<String> {
+    let mut tokens = HashSet::new();
+    for word in name.to_ascii_lowercase().split_whitespace() {
+        let fragments: Vec<&str> = FRAGMENT_RE.find_iter(word).map(|m| m.as_str()).collect();
+        if fragments.is_empty() {
+            continue;
+        }
+        tokens.insert(fragments.concat());
+        if fragments.len() > 1 {
+            for fragment in fragments {
+                if fragment.len() > 1 {
+                    tokens.insert(fragment.to_string());
+                }
+            }
+        }
+    }
+    tokens
+}
+
+/// Splits a vehicle name into every run of letters or digits, as search did
+/// before `tokenise`, e.g. "320d" -> "320", "d". Nothing is joined or dropped.
+pub fn fragments(name: &str) -> HashSet<String> {
+    FRAGMENT_RE
+        .find_iter(&name.to_ascii_lowercase())
+        .map(|m| m.as_str().to_string())
+        .collect()
+}
+
+/// Filters tokens down to the keywords to index a vehicle under.
+pub fn remove_stop_words(tokens: &HashSet<String>) -> HashSet<String> {
+    tokens
+        .iter()
+        .filter(|t| !is_stop_word(t))
+        .cloned()
+        .collect()
+}
+
+/// Version marker written as the first byte of every posting list blob.
+pub const POSTINGS_VERSION: u8 = 1;
+
+/// Encodes a posting list for the `bytes` column of a keywords table.
+///
+/// The blob starts with a single `POSTINGS_VERSION` byte. Each id follows in
+/// ascending order, stored as the difference from the previous id (the first
+/// is relative to zero) in unsigned LEB128: seven bits per byte, least
+/// significant group first, with the high bit set on every byte except the
+/// last. Ids close together therefore usually take a single byte, rather than
+/// the four bytes per id of the unversioned little-endian `u32` format this
+/// replaced.
+pub fn encode_postings(ids: &BTreeSet<u32>) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(ids.len() + 1);
+    ret.push(POSTINGS_VERSION);
+    let mut previous = 0;
+    for id in ids {
+        let mut delta = id - previous;
+        while delta >= 0x80 {
+            ret.push((delta & 0x7f) as u8 | 0x80);
+            delta >>= 7;
+        }
+        ret.push(delta as u8);
+        previous = *id;
+    }
+    ret
+}
+
+/// Encodes a posting list in the format of schema version 1, which has no
+/// version byte: each id in ascending order as a little-endian `u32`.
+pub fn encode_raw_postings(ids: &BTreeSet<u32>) -> Vec<u8> {
+    ids.iter().flat_map(|id| id.to_le_bytes()).collect()
+}
+
+/// Decodes a posting list written by `encode_postings`.
+pub fn decode_postings(bytes: &[u8]) -> Result<Vec<u32>, String> {
+    match bytes.first() {
+        Some(&POSTINGS_VERSION) => {}
+        Some(v) => return Err(format!("Unsupported posting list version: {}", v)),
+        None => return Err("Empty posting list".to_string()),
+    }
+    let mut ids = Vec::new();
+    let mut previous: u32 = 0;
+    let mut delta: u64 = 0;
+    let mut shift = 0;
+    for byte in &bytes[1..] {
+        delta |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            previous = u32::try_from(delta)
+                .ok()
+                .and_then(|d| previous.checked_add(d))
+                .ok_or("Posting list id overflows u32")?;
+            ids.push(previous);
+            delta = 0;
+            shift = 0;
+        } else if shift >= 28 {
+            return Err("Posting list delta overflows u32".to_string());
+        } else {
+            shift += 7;
+        }
+    }
+    if shift != 0 {
+        return Err("Truncated posting list".to_string());
+    }
+    Ok(ids)
+}
+
+/// Keyword posting lists and metaphone lookups for one id space.
+#[derive(Default)]
+pub struct SearchIndex {
+    keywords: BTreeMap<String, BTreeSet<u32>>,
+    metaphones: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl SearchIndex {
+    pub fn insert(&mut self, id: u32, words: HashSet<String>) {
+        for word in words {
+            self.keywords.entry(word.clone()).or_default().insert(id);
+            if word.len() > 4 {
+                if let Some(res) = double_metaphone(&word) {
+                    self.metaphones
+                        .entry(res.primary)
+                        .or_default()
+                        .insert(word.clone());
+                    self.metaphones
+                        .entry(res.alternate)
+                        .or_default()
+                        .insert(word);
+                }
+            }
+        }
+    }
+
+    /// Ids with each keyword.
+    pub fn keywords(&self) -> &BTreeMap<String, BTreeSet<u32>> {
+        &self.keywords
+    }
+
+    /// Keywords with each metaphone code.
+    pub fn metaphones(&self) -> &BTreeMap<String, BTreeSet<String>> {
+        &self.metaphones
+    }
+
+    /// Fills in the keywords and metaphones tables, writing posting lists in
+    /// the unversioned format of schema version 1 if `raw_postings` is set.
+    pub fn save(
+        &self,
+        db: &Connection,
+        keywords_table: &str,
+        metaphones_table: &str,
+        raw_postings: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        {
+            let mut stmt = db.prepare(&format!("INSERT INTO {keywords_table} VALUES (?1, ?2)"))?;
+            for (word, ids) in &self.keywords {
+                let blob = if raw_postings {
+                    encode_raw_postings(ids)
+                } else {
+                    let blob = encode_postings(ids);
+                    debug_assert!(decode_postings(&blob).is_ok_and(|d| d.iter().eq(ids.iter())));
+                    blob
+                };
+                stmt.execute((word, blob))?;
+            }
+        }
+        {
+            let mut stmt =
+                db.prepare(&format!("INSERT INTO {metaphones_table} VALUES (?1, ?2)"))?;
+            for (metaphone, words) in &self.metaphones {
+                stmt.execute((
+                    metaphone,
+                    words.iter().cloned().collect::<Vec<String>>().join("|"),
+                ))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(words: &[&str]) -> HashSet<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn joined_model_code() {
+        assert_eq!(tokenise("320d"), set(&["320d", "320"]));
+    }
+
+    #[test]
+    fn hyphenated_model_code() {
+        assert_eq!(tokenise("MX-5"), set(&["mx5", "mx"]));
+    }
+
+    #[test]
+    fn several_words() {
+        assert_eq!(tokenise("golf gti 16v"), set(&["golf", "gti", "16v", "16"]));
+    }
+
+    #[test]
+    fn punctuation_only_words_are_skipped() {
+        assert_eq!(tokenise("clio - 1.2"), set(&["clio", "12"]));
+    }
+
+    #[test]
+    fn stop_words_are_removed() {
+        let tokens = tokenise("the a4 avant s line 2.0 tdi auto");
+        assert!(tokens.contains("s"));
+        assert_eq!(
+            remove_stop_words(&tokens),
+            set(&["a4", "avant", "line", "20", "tdi", "auto"])
+        );
+    }
+
+    fn ids(ids: &[u32]) -> BTreeSet<u32> {
+        ids.iter().copied().collect()
+    }
+
+    #[test]
+    fn postings_round_trip() {
+        let ids = ids(&[3, 4, 200, 201, 70_000, 1 << 28]);
+        let decoded = decode_postings(&encode_postings(&ids)).unwrap();
+        assert_eq!(decoded, ids.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn empty_postings() {
+        assert_eq!(encode_postings(&ids(&[])), vec![POSTINGS_VERSION]);
+        assert_eq!(decode_postings(&[POSTINGS_VERSION]), Ok(vec![]));
+    }
+
+    #[test]
+    fn postings_at_the_limits() {
+        assert_eq!(encode_postings(&ids(&[0])), vec![POSTINGS_VERSION, 0]);
+        let ids = ids(&[0, 1, u32::MAX - 1, u32::MAX]);
+        let decoded = decode_postings(&encode_postings(&ids)).unwrap();
+        assert_eq!(decoded, vec![0, 1, u32::MAX - 1, u32::MAX]);
+    }
+
+    #[test]
+    fn truncated_postings() {
+        let mut blob = encode_postings(&ids(&[300]));
+        blob.pop();
+        assert_eq!(
+            decode_postings(&blob),
+            Err("Truncated posting list".to_string())
+        );
+    }
+
+    #[test]
+    fn unknown_postings_version() {
+        assert_eq!(
+            decode_postings(&[2, 1]),
+            Err("Unsupported posting list version: 2".to_string())
+        );
+        assert_eq!(decode_postings(&[]), Err("Empty posting list".to_string()));
+    }
+
+    #[test]
+    fn postings_are_smaller_than_raw() {
+        let ids: BTreeSet<u32> = (1000..1100).map(|i| i * 3).collect();
+        let encoded = encode_postings(&ids);
+        let raw = encode_raw_postings(&ids);
+        assert_eq!(raw.len(), 400);
+        // A version byte, two bytes for the first id and one for each delta.
+        assert_eq!(encoded.len(), 1 + 2 + 99);
+    }
+}
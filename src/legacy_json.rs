@@ -0,0 +1,99 @@
+use crate::Link;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Converts an entity's JSON to the layout of schema version 1: the entity's
+/// names and links, and the original stats as plain maps of positive counts,
+/// without flags, gaps, breakdowns added since, metrics or forecasts.
+pub fn entity_json(kind: &str, json: &str) -> Result<String, Box<dyn Error>> {
+    Ok(match kind {
+        "makes" => serde_json::to_string(&serde_json::from_str::<Make>(json)?)?,
+        "generic_models" => serde_json::to_string(&serde_json::from_str::<GenericModel>(json)?)?,
+        "models" => serde_json::to_string(&serde_json::from_str::<Model>(json)?)?,
+        _ => return Err(format!("Unknown entity kind: {}", kind).into()),
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+struct Make {
+    name: String,
+    slug: String,
+    generic_models: Vec<Link>,
+    #[serde(flatten)]
+    stats: Stats,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GenericModel {
+    name: String,
+    slug: String,
+    make: Link,
+    models: Vec<Link>,
+    #[serde(flatten)]
+    stats: Stats,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Model {
+    name: String,
+    slug: String,
+    make: Link,
+    generic_model: Link,
+    #[serde(flatten)]
+    stats: Stats,
+}
+
+type Counts = BTreeMap<String, i32>;
+
+#[derive(Serialize, Deserialize)]
+struct Stats {
+    #[serde(deserialize_with = "counts")]
+    quarterly_licensed: Counts,
+    #[serde(deserialize_with = "counts")]
+    quarterly_sorn: Counts,
+
+    #[serde(deserialize_with = "counts")]
+    first_reg_licensed: Counts,
+    #[serde(deserialize_with = "counts")]
+    first_reg_sorn: Counts,
+
+    #[serde(deserialize_with = "counts")]
+    manufacture_licensed: Counts,
+    #[serde(deserialize_with = "counts")]
+    manufacture_sorn: Counts,
+
+    #[serde(deserialize_with = "counts")]
+    new_reg: Counts,
+
+    #[serde(deserialize_with = "counts")]
+    petrol_licensed: Counts,
+    #[serde(deserialize_with = "counts")]
+    petrol_sorn: Counts,
+
+    #[serde(deserialize_with = "counts")]
+    diesel_licensed: Counts,
+    #[serde(deserialize_with = "counts")]
+    diesel_sorn: Counts,
+
+    #[serde(deserialize_with = "counts")]
+    other_licensed: Counts,
+    #[serde(deserialize_with = "counts")]
+    other_sorn: Counts,
+}
+
+// Keeps the positive counts of a map of cells, which is all that version 1
+// ever had: zeros, flags and the nulls filling gaps in a series are dropped,
+// and partial totals are plain counts, as they always were.
+fn counts<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Counts, D::Error> {
+    let cells = BTreeMap::<String, Value>::deserialize(deserializer)?;
+    Ok(cells
+        .into_iter()
+        .filter_map(|(k, v)| {
+            let n = v.as_i64().or_else(|| v.get("partial")?.as_i64())?;
+            let n = i32::try_from(n).ok()?;
+            (n > 0).then_some((k, n))
+        })
+        .collect())
+}
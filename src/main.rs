@@ -1,39 +1,80 @@
-use csv::Reader;
-use nlp::phonetics::metaphone::double_metaphone::double_metaphone;
-use once_cell::sync::Lazy;
-use regex::Regex;
-use rusqlite::Connection;
-use serde::de::DeserializeOwned;
-use serde::Serialize;
-use std::collections::{BTreeMap, BTreeSet, HashSet};
-use std::{error::Error, fs::File};
+use rusqlite::{Connection, OpenFlags};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
+use std::{
+    error::Error,
+    fs::File,
+    path::{Path, PathBuf},
+};
 
 mod dft;
+mod export;
+mod forecast;
+mod ids;
+mod incremental;
+mod intern;
+mod json_schema;
+mod keywords;
+mod legacy_json;
+mod metadata;
+mod metrics;
+mod period;
+mod rankings;
+mod schema;
+mod series;
+mod staging;
+mod synonyms;
+mod tables;
+#[cfg(test)]
+mod testing;
 
-static KEYWORD_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"[a-z]+|[0-9]+").unwrap());
+use forecast::Forecast;
+use ids::Ids;
+use intern::{IdentityKey, InternedIdentity, Interner};
+use keywords::SearchIndex;
+use metrics::Metrics;
+use period::{Quarter, Year};
+use series::{Cell, Series};
+use staging::Staging;
+use tables::InputFile;
 
-static CURRENT_FULL_YEAR: &str = "2025";
+// GB tables are used for quarters before this, and UK tables from it onwards.
+const UK_CUTOVER: Quarter = Quarter::new(2014, 3);
 
-#[derive(Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+// Quarterly tables only have a Q4 column for each year before this.
+const QUARTERLY_FROM: Quarter = Quarter::new(2009, 1);
+
+static INPUT_DIR: &str = "tmp/csv";
+
+static DATABASE: &str = "howmanyleft.sqlite3";
+
+static STAGING_DATABASE: &str = "howmanyleft.staging.sqlite3";
+
+#[derive(Debug, Eq, Ord, PartialEq, PartialOrd, Serialize, Deserialize, JsonSchema)]
 pub struct Link {
-    pub slug: String,
-    pub name: String,
+    pub slug: Arc<str>,
+    pub name: Arc<str>,
 }
 
-#[derive(Debug, Serialize)]
+/// A make, e.g. Ford, with the stats of all its models.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct Make {
-    pub name: String,
-    pub slug: String,
+    pub name: Arc<str>,
+    pub slug: Arc<str>,
     pub generic_models: BTreeSet<Link>,
     #[serde(flatten)]
     pub stats: Stats,
 }
 
 impl Make {
-    fn new(name: &str, slug: &str) -> Self {
+    fn new(name: Arc<str>, slug: Arc<str>) -> Self {
         Make {
-            name: name.to_string(),
-            slug: slug.to_string(),
+            name,
+            slug,
             generic_models: BTreeSet::new(),
             stats: Stats::new(),
         }
@@ -45,25 +86,35 @@ impl Make {
             slug: self.slug.clone(),
         }
     }
+
+    /// Merges a partial build of the same make into this one.
+    fn merge(&mut self, other: Make) {
+        self.generic_models.extend(other.generic_models);
+        self.stats.merge(&other.stats);
+    }
 }
 
-#[derive(Debug, Serialize)]
+/// A range of models within a make, e.g. the Ford Fiesta, with the stats of
+/// all its models.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct GenericModel {
-    pub name: String,
-    pub slug: String,
+    pub name: Arc<str>,
+    pub slug: Arc<str>,
     pub make: Link,
     pub models: BTreeSet<Link>,
+    pub extinction: Option<Forecast>,
     #[serde(flatten)]
     pub stats: Stats,
 }
 
 impl GenericModel {
-    fn new(make: &Make, name: &str, slug: &str) -> Self {
+    fn new(make: &Make, name: Arc<str>, slug: Arc<str>) -> Self {
         GenericModel {
-            name: name.to_string(),
-            slug: slug.to_string(),
+            name,
+            slug,
             make: make.link(),
             models: BTreeSet::new(),
+            extinction: None,
             stats: Stats::new(),
         }
     }
@@ -74,14 +125,27 @@ impl GenericModel {
             slug: self.slug.clone(),
         }
     }
+
+    fn full_name(&self) -> String {
+        [&*self.make.name, &*self.name].join(" ")
+    }
+
+    /// Merges a partial build of the same generic model into this one.
+    fn merge(&mut self, other: GenericModel) {
+        self.models.extend(other.models);
+        self.stats.merge(&other.stats);
+    }
 }
 
-#[derive(Debug, Serialize)]
+/// A single model, e.g. the Ford Fiesta Zetec 1.25.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct Model {
-    pub name: String,
-    pub slug: String,
+    pub name: Arc<str>,
+    pub slug: Arc<str>,
     pub make: Link,
     pub generic_model: Link,
+    pub body_type: dft::BodyType,
+    pub extinction: Option<Forecast>,
     #[serde(flatten)]
     pub stats: Stats,
 }
@@ -90,14 +154,17 @@ impl Model {
     fn new(
         make: &Make,
         generic_model: &GenericModel,
-        name: &str,
-        slug: &str,
+        body_type: dft::BodyType,
+        name: Arc<str>,
+        slug: Arc<str>,
     ) -> Self {
         Model {
-            name: name.to_string(),
-            slug: slug.to_string(),
+            name,
+            slug,
             make: make.link(),
             generic_model: generic_model.link(),
+            body_type,
+            extinction: None,
             stats: Stats::new(),
         }
     }
@@ -110,72 +177,115 @@ impl Model {
     }
 
     fn full_name(&self) -> String {
-        [self.make.name.as_str(), self.name.as_str()].join(" ")
+        [&*self.make.name, &*self.name].join(" ")
     }
 
-    fn keywords(&self) -> HashSet<String> {
-        KEYWORD_RE
-            .find_iter(&self.full_name().to_ascii_lowercase())
-            .map(|m| m.as_str().to_string())
-            .collect()
+    /// Merges a partial build of the same model into this one. The body type
+    /// is kept from this one, as the first row seen sets it.
+    fn merge(&mut self, other: Model) {
+        self.stats.merge(&other.stats);
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct Stats {
-    pub quarterly_licensed: BTreeMap<String, i32>,
-    pub quarterly_sorn: BTreeMap<String, i32>,
+    pub quarterly_licensed: Series,
+    pub quarterly_sorn: Series,
+
+    pub first_reg_licensed: BTreeMap<Year, Cell>,
+    pub first_reg_sorn: BTreeMap<Year, Cell>,
 
-    pub first_reg_licensed: BTreeMap<String, i32>,
-    pub first_reg_sorn: BTreeMap<String, i32>,
+    pub manufacture_licensed: BTreeMap<Year, Cell>,
+    pub manufacture_sorn: BTreeMap<Year, Cell>,
 
-    pub manufacture_licensed: BTreeMap<String, i32>,
-    pub manufacture_sorn: BTreeMap<String, i32>,
+    pub new_reg: Series,
+    pub new_reg_colour: BTreeMap<String, Series>,
+    pub first_reg_age: BTreeMap<String, Series>,
 
-    pub new_reg: BTreeMap<String, i32>,
+    pub quarterly_private: Series,
+    pub quarterly_company: Series,
 
-    pub petrol_licensed: BTreeMap<String, i32>,
-    pub petrol_sorn: BTreeMap<String, i32>,
+    pub petrol_licensed: BTreeMap<String, Cell>,
+    pub petrol_sorn: BTreeMap<String, Cell>,
 
-    pub diesel_licensed: BTreeMap<String, i32>,
-    pub diesel_sorn: BTreeMap<String, i32>,
+    pub diesel_licensed: BTreeMap<String, Cell>,
+    pub diesel_sorn: BTreeMap<String, Cell>,
 
-    pub other_licensed: BTreeMap<String, i32>,
-    pub other_sorn: BTreeMap<String, i32>,
+    pub other_licensed: BTreeMap<String, Cell>,
+    pub other_sorn: BTreeMap<String, Cell>,
+
+    pub region_licensed: BTreeMap<String, Cell>,
+    pub local_authority_licensed: BTreeMap<String, Cell>,
+
+    #[serde(flatten)]
+    pub metrics: Metrics,
 }
 
 impl Stats {
     fn new() -> Stats {
         Stats {
-            quarterly_licensed: BTreeMap::new(),
-            quarterly_sorn: BTreeMap::new(),
+            quarterly_licensed: Series::default(),
+            quarterly_sorn: Series::default(),
             first_reg_licensed: BTreeMap::new(),
             first_reg_sorn: BTreeMap::new(),
             manufacture_licensed: BTreeMap::new(),
             manufacture_sorn: BTreeMap::new(),
-            new_reg: BTreeMap::new(),
+            new_reg: Series::default(),
+            new_reg_colour: BTreeMap::new(),
+            first_reg_age: BTreeMap::new(),
+            quarterly_private: Series::default(),
+            quarterly_company: Series::default(),
             petrol_licensed: BTreeMap::new(),
             petrol_sorn: BTreeMap::new(),
             diesel_licensed: BTreeMap::new(),
             diesel_sorn: BTreeMap::new(),
             other_licensed: BTreeMap::new(),
             other_sorn: BTreeMap::new(),
+            region_licensed: BTreeMap::new(),
+            local_authority_licensed: BTreeMap::new(),
+            metrics: Metrics::default(),
         }
     }
 
+    fn compute_metrics(&mut self) {
+        self.metrics = Metrics::new(self);
+    }
+
+    /// Adds the counts from another set of stats for the same entity into
+    /// these. Metrics aren't merged, as they're computed after loading.
+    fn merge(&mut self, other: &Stats) {
+        self.quarterly_licensed.merge(&other.quarterly_licensed);
+        self.quarterly_sorn.merge(&other.quarterly_sorn);
+        series::merge(&mut self.first_reg_licensed, &other.first_reg_licensed);
+        series::merge(&mut self.first_reg_sorn, &other.first_reg_sorn);
+        series::merge(&mut self.manufacture_licensed, &other.manufacture_licensed);
+        series::merge(&mut self.manufacture_sorn, &other.manufacture_sorn);
+        self.new_reg.merge(&other.new_reg);
+        series::merge_series(&mut self.new_reg_colour, &other.new_reg_colour);
+        series::merge_series(&mut self.first_reg_age, &other.first_reg_age);
+        self.quarterly_private.merge(&other.quarterly_private);
+        self.quarterly_company.merge(&other.quarterly_company);
+        series::merge(&mut self.petrol_licensed, &other.petrol_licensed);
+        series::merge(&mut self.petrol_sorn, &other.petrol_sorn);
+        series::merge(&mut self.diesel_licensed, &other.diesel_licensed);
+        series::merge(&mut self.diesel_sorn, &other.diesel_sorn);
+        series::merge(&mut self.other_licensed, &other.other_licensed);
+        series::merge(&mut self.other_sorn, &other.other_sorn);
+        series::merge(&mut self.region_licensed, &other.region_licensed);
+        series::merge(
+            &mut self.local_authority_licensed,
+            &other.local_authority_licensed,
+        );
+    }
+
     fn merge_veh0120_gb(&mut self, row: &dft::Veh0120) -> Result<(), Box<dyn Error>> {
         for (k, v) in row.extra.iter() {
-            if *v <= 0 {
-                continue;
-            }
-            // Only use GB values from pre-2014Q3.
-            if "2014Q3".gt(k) {
-                let k2 = k.replace("Q", " q");
+            // Only use GB values from before the cutover.
+            let q: Quarter = k.parse()?;
+            if q < UK_CUTOVER {
                 match &row.licence_status {
-                    dft::LicenceStatus::Licensed => {
-                        *self.quarterly_licensed.entry(k2).or_insert(0) += v
-                    }
-                    dft::LicenceStatus::SORN => *self.quarterly_sorn.entry(k2).or_insert(0) += v,
+                    dft::LicenceStatus::Licensed => self.quarterly_licensed.add(q, v.into()),
+                    dft::LicenceStatus::SORN => self.quarterly_sorn.add(q, v.into()),
                 }
             }
         }
@@ -184,29 +294,41 @@ impl Stats {
 
     fn merge_veh0120_uk(&mut self, row: &dft::Veh0120) -> Result<(), Box<dyn Error>> {
         for (k, v) in row.extra.iter() {
-            if *v <= 0 {
-                continue;
-            }
-            let k2 = k.replace("Q", " q");
+            let q: Quarter = k.parse()?;
             match &row.licence_status {
-                dft::LicenceStatus::Licensed => {
-                    *self.quarterly_licensed.entry(k2).or_insert(0) += v
-                }
-                dft::LicenceStatus::SORN => *self.quarterly_sorn.entry(k2).or_insert(0) += v,
+                dft::LicenceStatus::Licensed => self.quarterly_licensed.add(q, v.into()),
+                dft::LicenceStatus::SORN => self.quarterly_sorn.add(q, v.into()),
             }
         }
         Ok(())
     }
 
-    fn merge_veh0160_gb(&mut self, row: &dft::Veh0160) -> Result<(), Box<dyn Error>> {
+    fn merge_veh0125(&mut self, row: &dft::Veh0125) -> Result<(), Box<dyn Error>> {
         for (k, v) in row.extra.iter() {
-            if *v <= 0 {
-                continue;
+            let q: Quarter = k.parse()?;
+            match &row.keeper_type {
+                dft::KeeperType::Private => self.quarterly_private.add(q, v.into()),
+                dft::KeeperType::Company => self.quarterly_company.add(q, v.into()),
             }
-            // Only use GB values from pre-2014Q3.
-            if "2014Q3".gt(k) {
-                let k2 = k.replace("Q", " q");
-                *self.new_reg.entry(k2).or_insert(0) += v;
+        }
+        Ok(())
+    }
+
+    fn merge_veh0150(&mut self, row: &dft::Veh0150) -> Result<(), Box<dyn Error>> {
+        let series = series::named_series(&mut self.new_reg_colour, &row.colour);
+        for (k, v) in row.extra.iter() {
+            let q: Quarter = k.parse()?;
+            series.add(q, v.into());
+        }
+        Ok(())
+    }
+
+    fn merge_veh0160_gb(&mut self, row: &dft::Veh0160) -> Result<(), Box<dyn Error>> {
+        for (k, v) in row.extra.iter() {
+            // Only use GB values from before the cutover.
+            let q: Quarter = k.parse()?;
+            if q < UK_CUTOVER {
+                self.new_reg.add(q, v.into());
             }
         }
         Ok(())
@@ -214,119 +336,255 @@ impl Stats {
 
     fn merge_veh0160_uk(&mut self, row: &dft::Veh0160) -> Result<(), Box<dyn Error>> {
         for (k, v) in row.extra.iter() {
-            if *v <= 0 {
-                continue;
-            }
-            let k2 = k.replace("Q", " q");
-            *self.new_reg.entry(k2).or_insert(0) += v;
+            let q: Quarter = k.parse()?;
+            self.new_reg.add(q, v.into());
+        }
+        Ok(())
+    }
+
+    fn merge_veh0121(&mut self, row: &dft::Veh0121) -> Result<(), Box<dyn Error>> {
+        if let Some(cell) = latest_quarter(&row.extra)? {
+            series::add_named(&mut self.region_licensed, &row.region, cell);
+        }
+        Ok(())
+    }
+
+    fn merge_veh0122(&mut self, row: &dft::Veh0122) -> Result<(), Box<dyn Error>> {
+        if let Some(cell) = latest_quarter(&row.extra)? {
+            series::add_named(
+                &mut self.local_authority_licensed,
+                &row.local_authority,
+                cell,
+            );
         }
         Ok(())
     }
 
     fn merge_veh0124(&mut self, row: &dft::Veh0124) -> Result<(), Box<dyn Error>> {
-        // TODO: Yearly breakdowns, not just current year.
-        if let Some(dft::OptionalNumber::Count(n)) = row.extra.get(CURRENT_FULL_YEAR) {
-            if *n <= 0 {
-                return Ok(());
-            }
-            let mk = match row.manufactured {
-                dft::OptionalNumber::Count(y) => y.to_string(),
-                _ => "Unknown".to_string(),
-            };
-            let fk = match row.first_used {
-                dft::OptionalNumber::Count(y) => y.to_string(),
-                _ => "Unknown".to_string(),
-            };
+        // TODO: Yearly breakdowns, not just the latest year.
+        if let Some(cell) = latest_year(&row.extra)? {
+            let mk = Year::from(&row.manufactured);
+            let fk = Year::from(&row.first_used);
             match &row.licence_status {
                 dft::LicenceStatus::Licensed => {
-                    *self.manufacture_licensed.entry(mk).or_insert(0) += n;
-                    *self.first_reg_licensed.entry(fk).or_insert(0) += n;
+                    series::add(&mut self.manufacture_licensed, mk, cell);
+                    series::add(&mut self.first_reg_licensed, fk, cell);
                 }
                 dft::LicenceStatus::SORN => {
-                    *self.manufacture_sorn.entry(mk).or_insert(0) += n;
-                    *self.first_reg_sorn.entry(fk).or_insert(0) += n;
+                    series::add(&mut self.manufacture_sorn, mk, cell);
+                    series::add(&mut self.first_reg_sorn, fk, cell);
                 }
             }
         }
         Ok(())
     }
 
+    fn merge_veh0170(&mut self, row: &dft::Veh0170) -> Result<(), Box<dyn Error>> {
+        let series = series::named_series(&mut self.first_reg_age, &row.age);
+        for (k, v) in row.extra.iter() {
+            let q: Quarter = k.parse()?;
+            series.add(q, v.into());
+        }
+        Ok(())
+    }
+
     fn merge_veh0220(&mut self, row: &dft::Veh0220) -> Result<(), Box<dyn Error>> {
-        // TODO: Yearly breakdowns, not just current year.
-        if let Some(n) = row.extra.get(CURRENT_FULL_YEAR) {
-            if *n <= 0 {
-                return Ok(());
-            }
+        // TODO: Yearly breakdowns, not just the latest year.
+        if let Some(cell) = latest_year(&row.extra)? {
             let engine_size = if row.engine_size_desc == "[z]" || row.engine_size_desc == "[x]" {
                 "Unknown"
             } else {
                 &row.engine_size_desc
-            }
-            .to_string();
-            match (&row.licence_status, &row.fuel) {
-                (dft::LicenceStatus::Licensed, dft::FuelType::Petrol) => {
-                    *self.petrol_licensed.entry(engine_size).or_insert(0) += n;
-                }
-                (dft::LicenceStatus::SORN, dft::FuelType::Petrol) => {
-                    *self.petrol_sorn.entry(engine_size).or_insert(0) += n;
-                }
-                (dft::LicenceStatus::Licensed, dft::FuelType::Diesel) => {
-                    *self.diesel_licensed.entry(engine_size).or_insert(0) += n;
-                }
-                (dft::LicenceStatus::SORN, dft::FuelType::Diesel) => {
-                    *self.diesel_sorn.entry(engine_size).or_insert(0) += n;
-                }
-                (dft::LicenceStatus::Licensed, _) => {
-                    *self.other_licensed.entry(engine_size).or_insert(0) += n;
-                }
-                (dft::LicenceStatus::SORN, _) => {
-                    *self.other_sorn.entry(engine_size).or_insert(0) += n;
-                }
-            }
+            };
+            let breakdown = match (&row.licence_status, &row.fuel) {
+                (dft::LicenceStatus::Licensed, dft::FuelType::Petrol) => &mut self.petrol_licensed,
+                (dft::LicenceStatus::SORN, dft::FuelType::Petrol) => &mut self.petrol_sorn,
+                (dft::LicenceStatus::Licensed, dft::FuelType::Diesel) => &mut self.diesel_licensed,
+                (dft::LicenceStatus::SORN, dft::FuelType::Diesel) => &mut self.diesel_sorn,
+                (dft::LicenceStatus::Licensed, _) => &mut self.other_licensed,
+                (dft::LicenceStatus::SORN, _) => &mut self.other_sorn,
+            };
+            series::add_named(breakdown, engine_size, cell);
         }
         Ok(())
     }
+
+    /// Whether no table has added anything to these stats.
+    fn is_empty(&self) -> bool {
+        self.quarterly_licensed.is_empty()
+            && self.quarterly_sorn.is_empty()
+            && self.first_reg_licensed.is_empty()
+            && self.first_reg_sorn.is_empty()
+            && self.manufacture_licensed.is_empty()
+            && self.manufacture_sorn.is_empty()
+            && self.new_reg.is_empty()
+            && self.new_reg_colour.is_empty()
+            && self.first_reg_age.is_empty()
+            && self.quarterly_private.is_empty()
+            && self.quarterly_company.is_empty()
+            && self.petrol_licensed.is_empty()
+            && self.petrol_sorn.is_empty()
+            && self.diesel_licensed.is_empty()
+            && self.diesel_sorn.is_empty()
+            && self.other_licensed.is_empty()
+            && self.other_sorn.is_empty()
+            && self.region_licensed.is_empty()
+            && self.local_authority_licensed.is_empty()
+    }
+
+    fn clear_veh0120(&mut self) {
+        self.quarterly_licensed = Series::default();
+        self.quarterly_sorn = Series::default();
+    }
+
+    fn clear_veh0121(&mut self) {
+        self.region_licensed.clear();
+    }
+
+    fn clear_veh0122(&mut self) {
+        self.local_authority_licensed.clear();
+    }
+
+    fn clear_veh0124(&mut self) {
+        self.manufacture_licensed.clear();
+        self.manufacture_sorn.clear();
+        self.first_reg_licensed.clear();
+        self.first_reg_sorn.clear();
+    }
+
+    fn clear_veh0125(&mut self) {
+        self.quarterly_private = Series::default();
+        self.quarterly_company = Series::default();
+    }
+
+    fn clear_veh0150(&mut self) {
+        self.new_reg_colour.clear();
+    }
+
+    fn clear_veh0160(&mut self) {
+        self.new_reg = Series::default();
+    }
+
+    fn clear_veh0170(&mut self) {
+        self.first_reg_age.clear();
+    }
+
+    fn clear_veh0220(&mut self) {
+        self.petrol_licensed.clear();
+        self.petrol_sorn.clear();
+        self.diesel_licensed.clear();
+        self.diesel_sorn.clear();
+        self.other_licensed.clear();
+        self.other_sorn.clear();
+    }
+}
+
+/// The value in the latest quarter column of a row.
+fn latest_quarter(
+    extra: &HashMap<String, dft::OptionalNumber>,
+) -> Result<Option<Cell>, Box<dyn Error>> {
+    latest_column(extra, str::parse::<Quarter>)
+}
+
+/// The value in the latest year column of a row. Every row of a table has the
+/// same columns, so this is the same year for all of them.
+fn latest_year(
+    extra: &HashMap<String, dft::OptionalNumber>,
+) -> Result<Option<Cell>, Box<dyn Error>> {
+    latest_column(extra, tables::parse_year)
+}
+
+fn latest_column<K: Ord>(
+    extra: &HashMap<String, dft::OptionalNumber>,
+    parse: fn(&str) -> Result<K, String>,
+) -> Result<Option<Cell>, Box<dyn Error>> {
+    let mut latest: Option<(K, &dft::OptionalNumber)> = None;
+    for (k, v) in extra.iter() {
+        let k = parse(k)?;
+        if latest.as_ref().is_none_or(|(l, _)| k > *l) {
+            latest = Some((k, v));
+        }
+    }
+    Ok(latest.map(|(_, v)| Cell::from(v)))
 }
 
 fn slugify(parts: &[&str]) -> Result<String, String> {
-    if !parts.iter().all(|s| s.chars().all(|c| c.is_ascii())) {
+    if !parts.iter().all(|s| s.is_ascii()) {
         Err(format!("Invalid characters in name: {:?}", parts))
     } else {
         Ok(parts
             .iter()
-            .map(|p| p.to_lowercase().replace(&[' ', '/'], "_"))
+            .map(|p| p.to_lowercase().replace([' ', '/'], "_"))
             .collect::<Vec<_>>()
             .join("_"))
     }
 }
 
-fn read_table<T, F>(filename: &str, mut callback: F) -> Result<(), Box<dyn Error>>
-where
-    T: DeserializeOwned,
-    F: FnMut(T) -> Result<(), Box<dyn Error>>,
-{
-    let reader = Reader::from_reader(File::open(filename)?);
-    for result in reader.into_deserialize() {
-        callback(result?)?;
-    }
-    Ok(())
+/// Search terms for a vehicle name: its keywords plus any synonyms.
+fn search_terms(name: &str) -> HashSet<String> {
+    let tokens = keywords::tokenise(name);
+    let mut words = keywords::remove_stop_words(&tokens);
+    words.extend(synonyms::expand(&tokens));
+    words
 }
 
-fn to_blob(indices: &BTreeSet<u32>) -> Vec<u8> {
-    let mut ret = Vec::with_capacity(indices.len() * 4);
-    for index in indices {
-        ret.push((index & 255) as u8);
-        ret.push(((index >> 8) & 255) as u8);
-        ret.push(((index >> 16) & 255) as u8);
-        ret.push(((index >> 24) & 255) as u8);
+type Merge<R> = fn(&mut Stats, &R) -> Result<(), Box<dyn Error>>;
+
+type SeriesField = fn(&Stats) -> &Series;
+
+/// Quarters seen in the quarterly series of every make, to warn about gaps.
+#[derive(Default)]
+struct Continuity {
+    seen: [BTreeSet<Quarter>; 3],
+}
+
+impl Continuity {
+    const SERIES: [(&'static str, SeriesField); 3] = [
+        ("quarterly_licensed", |s| &s.quarterly_licensed),
+        ("quarterly_sorn", |s| &s.quarterly_sorn),
+        ("new_reg", |s| &s.new_reg),
+    ];
+
+    fn add(&mut self, stats: &Stats) {
+        for (seen, (_, get)) in self.seen.iter_mut().zip(Self::SERIES) {
+            seen.extend(get(stats).quarters());
+        }
+    }
+
+    /// Warns about quarters since DfT went quarterly that no table had a
+    /// column for, which show up as gaps in every series.
+    fn check(&self) {
+        for (seen, (name, _)) in self.seen.iter().zip(Self::SERIES) {
+            let Some(last) = seen.last() else {
+                continue;
+            };
+            let mut quarter = QUARTERLY_FROM;
+            while quarter <= *last {
+                if !seen.contains(&quarter) {
+                    println!("Warning: no {} data for {}", name, quarter);
+                }
+                quarter = quarter.next();
+            }
+        }
     }
-    ret
 }
 
 struct Index {
-    makes: BTreeMap<String, Make>,
-    generic_models: BTreeMap<String, GenericModel>,
-    models: BTreeMap<String, Model>,
+    makes: BTreeMap<Arc<str>, Make>,
+    generic_models: BTreeMap<Arc<str>, GenericModel>,
+    models: BTreeMap<Arc<str>, Model>,
+
+    // Used while loading, so that rows for a vehicle that's already been seen
+    // go straight to its model without slugifying or allocating anything.
+    strings: Interner,
+    identities: HashMap<InternedIdentity, Arc<str>>,
+
+    // Only set for a streaming build.
+    staging: Option<Staging>,
+
+    // Every file in the input directory, including any that an incremental
+    // build didn't need to load.
+    inputs: Vec<InputFile>,
 }
 
 impl Index {
@@ -335,164 +593,549 @@ impl Index {
             models: BTreeMap::new(),
             generic_models: BTreeMap::new(),
             makes: BTreeMap::new(),
+            strings: Interner::default(),
+            identities: HashMap::new(),
+            staging: None,
+            inputs: Vec::new(),
         }
     }
 
-    fn insert<R>(
-        &mut self,
-        row: R,
-        update: fn(&mut Stats, &R) -> Result<(), Box<dyn Error>>,
-    ) -> Result<(), Box<dyn Error>>
+    fn insert<R>(&mut self, row: R, update: Merge<R>) -> Result<(), Box<dyn Error>>
     where
-        R: dft::HasIdentity + Clone,
+        R: dft::HasIdentity,
     {
-        let dft::VehicleIdentity {
+        let identity = row.identity();
+        let model_slug = match self.identities.get(&identity as &dyn IdentityKey) {
+            Some(model_slug) => model_slug.clone(),
+            None => {
+                let model_slug = self.add(&identity)?;
+                let key = InternedIdentity::new(&identity, &mut self.strings);
+                self.identities.insert(key, model_slug.clone());
+                model_slug
+            }
+        };
+        // Only the model's stats are updated here; `roll_up` adds them into
+        // the generic model and make once everything has been loaded.
+        update(&mut self.models.get_mut(&model_slug).unwrap().stats, &row)?;
+        if let Some(staging) = &mut self.staging {
+            if staging.row_loaded(&model_slug) {
+                staging.spill(&mut self.models)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Adds the entities for an identity that hasn't been seen before, if
+    // they aren't there already, links them together, and returns the slug of
+    // the model.
+    fn add(&mut self, identity: &dft::VehicleIdentity) -> Result<Arc<str>, String> {
+        let &dft::VehicleIdentity {
+            body_type,
             make: make_name,
             generic_model: generic_model_name,
             model: model_name,
-            ..
-        } = row.identity();
-        let make_slug = slugify(&[&make_name])?;
-        let generic_model_slug = slugify(&[&make_name, &generic_model_name])?;
-        let model_slug = slugify(&[&make_name, &model_name])?;
+        } = identity;
+        let strings = &mut self.strings;
+        let make_slug = strings.intern(&slugify(&[make_name])?);
+        let generic_model_slug = strings.intern(&slugify(&[make_name, generic_model_name])?);
+        let model_slug = strings.intern(&slugify(&[make_name, model_name])?);
         let make = self
             .makes
             .entry(make_slug.clone())
-            .or_insert_with(|| Make::new(make_name, &make_slug));
+            .or_insert_with(|| Make::new(strings.intern(make_name), make_slug.clone()));
         let generic_model = self
             .generic_models
             .entry(generic_model_slug.clone())
-            .or_insert_with(|| GenericModel::new(make, generic_model_name, &generic_model_slug));
+            .or_insert_with(|| {
+                GenericModel::new(
+                    make,
+                    strings.intern(generic_model_name),
+                    generic_model_slug.clone(),
+                )
+            });
         make.generic_models.insert(generic_model.link());
         let model = self.models.entry(model_slug.clone()).or_insert_with(|| {
             Model::new(
                 make,
                 generic_model,
-                model_name,
-                &model_slug,
+                body_type,
+                strings.intern(model_name),
+                model_slug.clone(),
             )
         });
         generic_model.models.insert(model.link());
-        update(&mut make.stats, &row)?;
-        update(&mut generic_model.stats, &row)?;
-        update(&mut model.stats, &row)?;
 
-        Ok(())
+        Ok(model_slug)
     }
 
-    fn save(&self) -> Result<(), Box<dyn Error>> {
-        let mut keywords: BTreeMap<String, BTreeSet<u32>> = BTreeMap::new();
-        let mut metaphones: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
-        {
-            let mut index: u32 = 1;
-            for model in self.models.values() {
-                for word in model.keywords() {
-                    keywords
-                        .entry(word.clone())
-                        .or_insert_with(|| BTreeSet::new())
-                        .insert(index);
-                    if word.len() > 4 {
-                        if let Some(res) = double_metaphone(&word) {
-                            metaphones
-                                .entry(res.primary)
-                                .or_insert_with(|| BTreeSet::new())
-                                .insert(word.clone());
-                            metaphones
-                                .entry(res.alternate)
-                                .or_insert_with(|| BTreeSet::new())
-                                .insert(word);
-                        }
-                    }
+    /// Merges a partial index, loaded from other files, into this one.
+    /// Entities already in this index keep their names and body types, and
+    /// every link is then pointed at the names that were kept, so merging
+    /// partials in load order gives the same result as loading every file
+    /// into one index. New entities have their names re-interned here, so
+    /// that `other`'s strings are freed with it. Nothing more can be loaded
+    /// into `other`'s entities, so its identity cache is dropped.
+    fn merge(&mut self, other: Index) {
+        let strings = &mut self.strings;
+        for (slug, mut make) in other.makes {
+            match self.makes.entry(strings.intern(&slug)) {
+                Entry::Occupied(mut e) => e.get_mut().merge(make),
+                Entry::Vacant(e) => {
+                    make.slug = e.key().clone();
+                    make.name = strings.intern(&make.name);
+                    e.insert(make);
+                }
+            }
+        }
+        for (slug, mut generic_model) in other.generic_models {
+            match self.generic_models.entry(strings.intern(&slug)) {
+                Entry::Occupied(mut e) => e.get_mut().merge(generic_model),
+                Entry::Vacant(e) => {
+                    generic_model.slug = e.key().clone();
+                    generic_model.name = strings.intern(&generic_model.name);
+                    e.insert(generic_model);
+                }
+            }
+        }
+        for (slug, mut model) in other.models {
+            match self.models.entry(strings.intern(&slug)) {
+                Entry::Occupied(mut e) => e.get_mut().merge(model),
+                Entry::Vacant(e) => {
+                    model.slug = e.key().clone();
+                    model.name = strings.intern(&model.name);
+                    e.insert(model);
                 }
-                index += 1;
             }
         }
+        self.relink();
+    }
+
+    // Rebuilds every link from the entities it points to. Names that differ
+    // only in what `slugify` drops, e.g. "KA/X" and "KA X", give the same
+    // entity, and a merge can bring in links to it under the name that lost.
+    fn relink(&mut self) {
+        for make in self.makes.values_mut() {
+            make.generic_models.clear();
+        }
+        for generic_model in self.generic_models.values_mut() {
+            generic_model.make = self.makes[&generic_model.make.slug].link();
+            generic_model.models.clear();
+        }
+        for model in self.models.values_mut() {
+            model.make = self.makes[&model.make.slug].link();
+            let generic_model = self
+                .generic_models
+                .get_mut(&model.generic_model.slug)
+                .unwrap();
+            model.generic_model = generic_model.link();
+            generic_model.models.insert(model.link());
+        }
+        for generic_model in self.generic_models.values() {
+            let make = self.makes.get_mut(&generic_model.make.slug).unwrap();
+            make.generic_models.insert(generic_model.link());
+        }
+    }
+
+    /// Adds the stats of every model into its generic model, and of every
+    /// generic model into its make.
+    fn roll_up(&mut self) {
+        for model in self.models.values() {
+            let generic_model = &model.generic_model.slug;
+            let generic_model = self.generic_models.get_mut(generic_model).unwrap();
+            generic_model.stats.merge(&model.stats);
+        }
+        for generic_model in self.generic_models.values() {
+            let make = self.makes.get_mut(&generic_model.make.slug).unwrap();
+            make.stats.merge(&generic_model.stats);
+        }
+    }
+
+    /// The latest quarter with a licensed count for any make.
+    fn latest_quarter(&self) -> Option<Quarter> {
+        self.makes
+            .values()
+            .filter_map(|m| m.stats.quarterly_licensed.quarters().next_back())
+            .max()
+    }
+
+    fn check_continuity(&self) {
+        let mut continuity = Continuity::default();
+        for make in self.makes.values() {
+            continuity.add(&make.stats);
+        }
+        continuity.check();
+    }
+
+    /// Computes the derived figures once all tables have been merged.
+    fn finalise(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(mut staging) = self.staging.take() {
+            let result = staging.finish(self);
+            self.staging = Some(staging);
+            return result;
+        }
+        self.check_continuity();
+        for make in self.makes.values_mut() {
+            make.stats.compute_metrics();
+        }
+        for generic_model in self.generic_models.values_mut() {
+            generic_model.stats.compute_metrics();
+            generic_model.extinction = forecast::forecast_stats(&generic_model.stats);
+        }
+        for model in self.models.values_mut() {
+            model.stats.compute_metrics();
+            model.extinction = forecast::forecast_stats(&model.stats);
+        }
+        Ok(())
+    }
 
-        let _ = std::fs::remove_file("howmanyleft.sqlite3");
-        let db = Connection::open("howmanyleft.sqlite3")?;
+    // An entity's JSON: serialized now or, in a streaming build, when its
+    // stats were finished.
+    fn json<T: Serialize>(
+        &self,
+        kind: &str,
+        slug: &str,
+        entity: &T,
+    ) -> Result<String, Box<dyn Error>> {
+        match &self.staging {
+            Some(staging) => staging.json(kind, slug),
+            None => Ok(serde_json::to_string(entity)?),
+        }
+    }
+
+    /// Search indexes of the makes, generic models and models, each by the
+    /// ids given for its slugs. Models are indexed as the original search
+    /// did if `original_keywords` is set.
+    fn search_indexes(
+        &self,
+        make_id: &BTreeMap<Arc<str>, u32>,
+        generic_model_id: &BTreeMap<Arc<str>, u32>,
+        model_id: &BTreeMap<Arc<str>, u32>,
+        original_keywords: bool,
+    ) -> [SearchIndex; 3] {
+        let mut make_search = SearchIndex::default();
+        for make in self.makes.values() {
+            make_search.insert(make_id[&make.slug], search_terms(&make.name));
+        }
+        let mut generic_model_search = SearchIndex::default();
+        for generic_model in self.generic_models.values() {
+            generic_model_search.insert(
+                generic_model_id[&generic_model.slug],
+                search_terms(&generic_model.full_name()),
+            );
+        }
+        let mut model_search = SearchIndex::default();
+        for model in self.models.values() {
+            let name = model.full_name();
+            let words = if original_keywords {
+                keywords::fragments(&name)
+            } else {
+                search_terms(&name)
+            };
+            model_search.insert(model_id[&model.slug], words);
+        }
+        [make_search, generic_model_search, model_search]
+    }
+
+    /// Writes the database, laid out as schema `version`, to a temporary file
+    /// and moves it into place, so that a failed save leaves the previous
+    /// database as it was.
+    ///
+    /// SQLite doesn't sync the temporary file, as it's written without a
+    /// journal, so it's synced here before the rename, and the directory
+    /// after it, so that a crash can't leave a database that's been renamed
+    /// into place but not written out.
+    fn save(&self, database: &Path, version: u32) -> Result<(), Box<dyn Error>> {
+        let mut temp = database.as_os_str().to_owned();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let _ = std::fs::remove_file(&temp);
+        if let Err(err) = self
+            .write(&temp, database, version)
+            .and_then(|()| Ok(File::open(&temp)?.sync_all()?))
+        {
+            let _ = std::fs::remove_file(&temp);
+            return Err(err);
+        }
+        std::fs::rename(&temp, database)?;
+        sync_dir(database)?;
+        Ok(())
+    }
+
+    // Writes the whole database to `path` in a single transaction, carrying
+    // ids over from the `previous` database, laid out as `version`.
+    fn write(&self, path: &Path, previous: &Path, version: u32) -> Result<(), Box<dyn Error>> {
+        let layout = schema::layout(version);
+
+        // Makes, generic models and models each have their own id space and
+        // search index.
+        let (mut make_ids, mut generic_model_ids, mut model_ids) = {
+            let previous = open_previous(previous)?;
+            (
+                Ids::load(previous.as_ref(), "makes")?,
+                Ids::load(previous.as_ref(), "generic_models")?,
+                Ids::load(previous.as_ref(), "models")?,
+            )
+        };
+        let make_id = make_ids.assign(self.makes.keys());
+        let generic_model_id = generic_model_ids.assign(self.generic_models.keys());
+        let model_id = model_ids.assign(self.models.keys());
+        let [make_search, generic_model_search, model_search] = self.search_indexes(
+            &make_id,
+            &generic_model_id,
+            &model_id,
+            layout.original_keywords,
+        );
+
+        // There's no need for a journal, as a failed write is thrown away.
+        let mut db = Connection::open(path)?;
         db.execute_batch(
             "PRAGMA journal_mode = OFF;
              PRAGMA synchronous = 0;
              PRAGMA cache_size = 1000000;
              PRAGMA locking_mode = EXCLUSIVE;
-             PRAGMA temp_store = MEMORY;
-             CREATE TABLE makes (slug VARCHAR(255) PRIMARY KEY, name VARCHAR(255), json TEXT);
-             CREATE TABLE generic_models (slug VARCHAR(255) PRIMARY KEY, json TEXT);
-             CREATE TABLE models (slug VARCHAR(255) PRIMARY KEY, id UNSIGNED INTEGER UNIQUE, json TEXT);
-             CREATE TABLE keywords (keyword VARCHAR(255) PRIMARY KEY, bytes BLOB);
-             CREATE TABLE metaphones (metaphone VARCHAR(255) PRIMARY KEY, data TEXT);",
+             PRAGMA temp_store = MEMORY;",
         )?;
+        let tx = db.transaction()?;
+        schema::create(&tx, version)?;
         {
-            let mut stmt = db.prepare("INSERT INTO makes VALUES (?1, ?2, ?3)")?;
+            let mut stmt = tx.prepare(if layout.entity_ids {
+                "INSERT INTO makes VALUES (?1, ?2, ?3, ?4)"
+            } else {
+                "INSERT INTO makes VALUES (?1, ?2, ?3)"
+            })?;
             for make in self.makes.values() {
-                stmt.execute([&make.slug, &make.name, &serde_json::to_string(&make)?])?;
+                let json = (layout.entity_json)("makes", self.json("makes", &make.slug, make)?)?;
+                if layout.entity_ids {
+                    stmt.execute((&make.slug, &make.name, &json, &make_id[&make.slug]))?;
+                } else {
+                    stmt.execute((&make.slug, &make.name, &json))?;
+                }
             }
         }
         {
-            let mut stmt = db.prepare("INSERT INTO generic_models VALUES (?1, ?2)")?;
+            let mut stmt = tx.prepare(if layout.entity_ids {
+                "INSERT INTO generic_models VALUES (?1, ?2, ?3)"
+            } else {
+                "INSERT INTO generic_models VALUES (?1, ?2)"
+            })?;
             for generic_model in self.generic_models.values() {
-                stmt.execute([&generic_model.slug, &serde_json::to_string(&generic_model)?])?;
+                let slug = &generic_model.slug;
+                let json = self.json("generic_models", slug, generic_model)?;
+                let json = (layout.entity_json)("generic_models", json)?;
+                if layout.entity_ids {
+                    stmt.execute((slug, &json, &generic_model_id[slug]))?;
+                } else {
+                    stmt.execute((slug, &json))?;
+                }
             }
         }
         {
-            let mut stmt = db.prepare("INSERT INTO models VALUES (?1, ?2, ?3)")?;
-            let mut index = 1;
+            let mut stmt = tx.prepare("INSERT INTO models VALUES (?1, ?2, ?3)")?;
             for model in self.models.values() {
-                stmt.execute((&model.slug, &index, &serde_json::to_string(&model)?))?;
-                index += 1;
+                let json = self.json("models", &model.slug, model)?;
+                let json = (layout.entity_json)("models", json)?;
+                stmt.execute((&model.slug, &model_id[&model.slug], &json))?;
             }
         }
-        {
-            let mut stmt = db.prepare("INSERT INTO keywords VALUES (?1, ?2)")?;
-            for (word, indices) in keywords {
-                stmt.execute((word, to_blob(&indices)))?;
-            }
+        model_search.save(&tx, "keywords", "metaphones", layout.raw_postings)?;
+        if layout.entity_ids {
+            make_search.save(&tx, "make_keywords", "make_metaphones", layout.raw_postings)?;
+            generic_model_search.save(
+                &tx,
+                "generic_model_keywords",
+                "generic_model_metaphones",
+                layout.raw_postings,
+            )?;
         }
-        {
-            let mut stmt = db.prepare("INSERT INTO metaphones VALUES (?1, ?2)")?;
-            for (metaphone, words) in metaphones {
-                stmt.execute((
-                    metaphone,
-                    words.clone().into_iter().collect::<Vec<String>>().join("|"),
-                ))?;
+        if layout.derived_tables {
+            rankings::save(self, &tx)?;
+            {
+                let mut stmt = tx.prepare("INSERT INTO id_sequences VALUES (?1, ?2)")?;
+                stmt.execute(("makes", make_ids.next_id()))?;
+                stmt.execute(("generic_models", generic_model_ids.next_id()))?;
+                stmt.execute(("models", model_ids.next_id()))?;
             }
+            metadata::save(self, &tx)?;
         }
+        tx.commit()?;
         Ok(())
     }
 }
 
-fn parse() -> Result<Index, Box<dyn Error>> {
-    let mut index = Index::new();
-    read_table("tmp/csv/df_VEH0120_GB.csv", |r| {
-        index.insert(r, |s, r| s.merge_veh0120_gb(r))
-    })?;
-    read_table("tmp/csv/df_VEH0120_UK.csv", |r| {
-        index.insert(r, |s, r| s.merge_veh0120_uk(r))
-    })?;
-    read_table("tmp/csv/df_VEH0124_AM.csv", |r| {
-        index.insert(r, |s, r| s.merge_veh0124(r))
-    })?;
-    read_table("tmp/csv/df_VEH0124_NZ.csv", |r| {
-        index.insert(r, |s, r| s.merge_veh0124(r))
-    })?;
-    read_table("tmp/csv/df_VEH0160_GB.csv", |r| {
-        index.insert(r, |s, r| s.merge_veh0160_gb(r))
-    })?;
-    read_table("tmp/csv/df_VEH0160_UK.csv", |r| {
-        index.insert(r, |s, r| s.merge_veh0160_uk(r))
-    })?;
-    read_table("tmp/csv/df_VEH0220.csv", |r| {
-        index.insert(r, |s, r| s.merge_veh0220(r))
-    })?;
+/// Opens the database from a previous build read-only, if there is one.
+fn open_previous(database: &Path) -> Result<Option<Connection>, rusqlite::Error> {
+    if !database.exists() {
+        return Ok(None);
+    }
+    Connection::open_with_flags(database, OpenFlags::SQLITE_OPEN_READ_ONLY).map(Some)
+}
+
+/// Syncs the directory that `path` is in, so that a file just renamed into it
+/// stays renamed after a crash. Only Unix can open a directory to sync it.
+fn sync_dir(path: &Path) -> std::io::Result<()> {
+    if cfg!(unix) {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
 
+/// Loads the tables in `input_dir`, or just those that have changed since
+/// the previous build in `database` for an incremental build. A streaming
+/// build spills to `staging`.
+fn parse(
+    input_dir: &Path,
+    database: &Path,
+    staging: Option<Staging>,
+    incremental: bool,
+) -> Result<Index, Box<dyn Error>> {
+    let streaming = staging.is_some();
+    let tables = tables::registry();
+    let inputs = tables::find(input_dir, &tables)?;
+    let mut files: Vec<InputFile> = inputs.iter().map(|input| input.file.clone()).collect();
+    let mut load: Vec<&tables::Input> = inputs.iter().collect();
+    let mut index = if incremental {
+        let Some(previous) = open_previous(database)? else {
+            return Err("no previous database for an incremental build".into());
+        };
+        let previous_files = metadata::input_files(&previous)?;
+        let sources = incremental::changed_sources(&tables, &inputs, &previous_files);
+        if sources.is_empty() {
+            println!("No tables changed since the previous build");
+        } else {
+            let sources: Vec<&str> = sources.iter().copied().collect();
+            println!("Changed since the previous build: {}", sources.join(", "));
+        }
+        load.retain(|input| sources.contains(input.table.source()));
+        // Files that aren't loaded again keep the row counts from last time.
+        for file in &mut files {
+            file.rows = previous_files
+                .iter()
+                .find(|previous| previous.same_as(file))
+                .and_then(|previous| previous.rows);
+        }
+        incremental::load_index(&previous, &tables, &sources)?
+    } else {
+        Index::new()
+    };
+    index.staging = staging;
+    let rows = tables::load(&load, &mut index)?;
+    for (input, rows) in load.iter().zip(rows) {
+        let file = files.iter_mut().find(|f| f.filename == input.file.filename);
+        let file = file.unwrap();
+        match rows {
+            Ok(rows) => file.rows = Some(rows),
+            Err(err) => file.skipped = Some(err),
+        }
+    }
+    index.inputs = files;
+    if !streaming {
+        index.roll_up();
+    }
     Ok(index)
 }
 
 fn main() {
-    match parse() {
-        Ok(index) => {
-            if let Err(err) = index.save() {
-                println!("Save error: {}", err);
+    // A streaming build spills stats to disk as it goes, so that its memory
+    // use doesn't grow with the number of quarters in the input. It still
+    // holds the names and links of every entity, so memory does grow with the
+    // number of models.
+    let streaming = std::env::args().any(|arg| arg == "--streaming");
+    // Rows a streaming build loads between spills.
+    let spill_rows = match std::env::args().find_map(|arg| {
+        arg.strip_prefix("--spill-rows=")
+            .map(|n| n.parse::<usize>().ok().filter(|n| *n > 0))
+    }) {
+        None => staging::DEFAULT_SPILL_ROWS,
+        Some(Some(n)) => n,
+        Some(None) => {
+            println!("--spill-rows needs a positive number of rows");
+            return;
+        }
+    };
+    // An incremental build starts from the previous database, and only
+    // reloads the tables whose input files have changed since.
+    let incremental = std::env::args().any(|arg| arg == "--incremental");
+    if streaming && incremental {
+        println!("Can't do a streaming build incrementally");
+        return;
+    }
+    // Older schema versions can be written for readers that haven't been
+    // upgraded yet.
+    let version = match std::env::args().find_map(|arg| {
+        arg.strip_prefix("--schema-version=")
+            .map(schema::parse_version)
+    }) {
+        None => schema::CURRENT_VERSION,
+        Some(Ok(version)) => version,
+        Some(Err(err)) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    // Prints the schema, as SQL with comments, instead of building.
+    if std::env::args().any(|arg| arg == "--schema") {
+        print!("{}", schema::describe(version));
+        return;
+    }
+    // Prints the JSON Schema, or TypeScript definitions, for the entity JSON
+    // in the database instead of building.
+    if std::env::args().any(|arg| arg == "--json-schema") {
+        println!("{:#}", json_schema::schema().as_value());
+        return;
+    }
+    if std::env::args().any(|arg| arg == "--typescript") {
+        print!("{}", json_schema::typescript(&json_schema::schema()));
+        return;
+    }
+    // Writes a tree of static JSON files to this directory instead of the
+    // database, for deployments that can't run SQLite.
+    let export_dir =
+        std::env::args().find_map(|arg| arg.strip_prefix("--export=").map(PathBuf::from));
+    // Prints how long each stage took, for script/bench.
+    let timings = std::env::args().any(|arg| arg == "--timings");
+    let timed = |stage: &str, start: Instant| {
+        if timings {
+            println!("{} in {:.2?}", stage, start.elapsed());
+        }
+    };
+    let staging = if streaming {
+        match Staging::create(Path::new(STAGING_DATABASE), spill_rows) {
+            Ok(staging) => Some(staging),
+            Err(err) => {
+                println!("Staging error: {}", err);
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    let database = Path::new(DATABASE);
+    let start = Instant::now();
+    match parse(Path::new(INPUT_DIR), database, staging, incremental) {
+        Ok(mut index) => {
+            timed("Parsed", start);
+            let start = Instant::now();
+            if let Err(err) = index.finalise() {
+                println!("Finalise error: {}", err);
+                return;
+            }
+            timed("Finalised", start);
+            let start = Instant::now();
+            if let Some(dir) = export_dir {
+                match export::export(&index, &dir) {
+                    Ok(()) => timed("Exported", start),
+                    Err(err) => println!("Export error: {}", err),
+                }
+                return;
+            }
+            match index.save(database, version) {
+                Ok(()) => timed("Saved", start),
+                Err(err) => println!("Save error: {}", err),
             }
         }
         Err(err) => {
@@ -500,3 +1143,534 @@ fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use testing::{build, dump, load, write_inputs, TempDir};
+
+    fn stats<'a>(index: &'a Index, slug: &str) -> &'a Stats {
+        &index.models[slug].stats
+    }
+
+    #[test]
+    fn numeric_names() {
+        let index = load(
+            "VEH0120 (UK)",
+            "BodyType,Make,GenModel,Model,Fuel,LicenceStatus,2024Q2,2024Q1\n\
+             Cars,PORSCHE,911,911,PETROL,Licensed,3,4\n\
+             Cars,PEUGEOT,308,1.60,PETROL,Licensed,1,1\n\
+             Cars,007,DB5,0911,PETROL,Licensed,1,1\n",
+        );
+        let model = &index.models["porsche_911"];
+        assert_eq!(&*model.name, "911");
+        assert_eq!(&*model.generic_model.name, "911");
+        assert_eq!(
+            model.stats.quarterly_licensed.count(Quarter::new(2024, 2)),
+            Some(3)
+        );
+        // Names that look like other kinds of number are kept as they are.
+        assert_eq!(&*index.models["peugeot_1.60"].name, "1.60");
+        assert_eq!(&*index.models["007_0911"].make.name, "007");
+    }
+
+    #[test]
+    fn veh0124_keeps_zeros_and_flags() {
+        let index = load(
+            "VEH0124",
+            "BodyType,Make,GenModel,Model,YearFirstUsed,YearManufacture,LicenceStatus,2025,2024\n\
+             Cars,FORD,KA,KA 1.3,1998,1997,Licensed,0,3\n\
+             Cars,FORD,KA,KA 1.3,1998,1997,SORN,[x],1\n\
+             Cars,FORD,KA,KA 1.3,1999,[x],Licensed,2,2\n",
+        );
+        let stats = stats(&index, "ford_ka_1.3");
+        assert_eq!(
+            stats.manufacture_licensed,
+            BTreeMap::from([
+                (Year::Known(1997), Cell::Count(0)),
+                (Year::Unknown, Cell::Count(2)),
+            ])
+        );
+        assert_eq!(
+            stats.first_reg_licensed,
+            BTreeMap::from([
+                (Year::Known(1998), Cell::Count(0)),
+                (Year::Known(1999), Cell::Count(2)),
+            ])
+        );
+        assert_eq!(
+            stats.manufacture_sorn,
+            BTreeMap::from([(Year::Known(1997), Cell::NotAvailable)])
+        );
+    }
+
+    #[test]
+    fn veh0124_uses_the_latest_year() {
+        let index = load(
+            "VEH0124",
+            "BodyType,Make,GenModel,Model,YearFirstUsed,YearManufacture,LicenceStatus,2025,2026,2024\n\
+             Cars,FORD,KA,KA 1.3,1998,1997,Licensed,5,6,4\n",
+        );
+        assert_eq!(
+            stats(&index, "ford_ka_1.3").manufacture_licensed,
+            BTreeMap::from([(Year::Known(1997), Cell::Count(6))])
+        );
+    }
+
+    #[test]
+    fn veh0220_keeps_zeros_and_flags() {
+        let index = load(
+            "VEH0220",
+            "BodyType,Make,GenModel,Model,Fuel,EngineSizeSimple,EngineSizeDesc,LicenceStatus,2025,2024\n\
+             Cars,FORD,KA,KA 1.3,PETROL,1,1001cc to 1500cc,Licensed,0,4\n\
+             Cars,FORD,KA,KA 1.3,PETROL,1,1001cc to 1500cc,SORN,[x],1\n\
+             Cars,FORD,KA,KA 1.3,Battery electric,[z],[z],Licensed,3,0\n",
+        );
+        let stats = stats(&index, "ford_ka_1.3");
+        assert_eq!(
+            stats.petrol_licensed,
+            BTreeMap::from([("1001cc to 1500cc".to_string(), Cell::Count(0))])
+        );
+        assert_eq!(
+            stats.petrol_sorn,
+            BTreeMap::from([("1001cc to 1500cc".to_string(), Cell::NotAvailable)])
+        );
+        assert_eq!(
+            stats.other_licensed,
+            BTreeMap::from([("Unknown".to_string(), Cell::Count(3))])
+        );
+    }
+
+    fn named(cells: &[(&str, Cell)]) -> BTreeMap<String, Cell> {
+        cells.iter().map(|(k, c)| (k.to_string(), *c)).collect()
+    }
+
+    #[test]
+    fn veh0121_latest_quarter_by_region() {
+        let mut index = load(
+            "VEH0121",
+            "BodyType,Make,GenModel,Model,ONS_Code,ONS_Geography,2024Q1,2024Q2,2023Q4\n\
+             Cars,FORD,KA,KA 1.3,E12000001,North East,5,7,9\n\
+             Cars,FORD,KA,KA 1.3,W92000004,Wales,5,0,9\n\
+             Cars,FORD,KA,KA 1.3,S92000003,Scotland,5,[x],9\n\
+             Cars,FORD,KA,KA 1.6,E12000001,North East,1,2,3\n\
+             Cars,FORD,KA,KA 1.6,S92000003,Scotland,1,4,3\n",
+        );
+        // Only the latest quarter counts, whatever the column order.
+        assert_eq!(
+            stats(&index, "ford_ka_1.3").region_licensed,
+            named(&[
+                ("North East", Cell::Count(7)),
+                ("Scotland", Cell::NotAvailable),
+                ("Wales", Cell::Count(0)),
+            ])
+        );
+        index.roll_up();
+        assert_eq!(
+            index.makes["ford"].stats.region_licensed,
+            named(&[
+                ("North East", Cell::Count(9)),
+                // KA 1.3's count for Scotland was withheld.
+                ("Scotland", Cell::Partial(4)),
+                ("Wales", Cell::Count(0)),
+            ])
+        );
+    }
+
+    #[test]
+    fn veh0150_colour_by_quarter() {
+        let mut index = load(
+            "VEH0150",
+            "BodyType,Make,GenModel,Model,Colour,2024Q2,2024Q1\n\
+             Cars,FORD,KA,KA 1.3,RED,3,0\n\
+             Cars,FORD,KA,KA 1.3,BLUE,[x],2\n\
+             Cars,FORD,KA,KA 1.6,RED,1,1\n",
+        );
+        let q1 = Quarter::new(2024, 1);
+        let q2 = Quarter::new(2024, 2);
+        let colours = &stats(&index, "ford_ka_1.3").new_reg_colour;
+        assert_eq!(colours.keys().collect::<Vec<_>>(), ["BLUE", "RED"]);
+        assert_eq!(colours["RED"].count(q1), Some(0));
+        assert_eq!(colours["RED"].count(q2), Some(3));
+        assert_eq!(colours["BLUE"].count(q1), Some(2));
+        assert_eq!(colours["BLUE"].count(q2), None);
+        assert_eq!(colours["BLUE"].quarters().collect::<Vec<_>>(), [q1, q2]);
+
+        index.roll_up();
+        let colours = &index.makes["ford"].stats.new_reg_colour;
+        assert_eq!(colours["RED"].count(q1), Some(1));
+        assert_eq!(colours["RED"].count(q2), Some(4));
+    }
+
+    #[test]
+    fn veh0170_age_by_quarter() {
+        let index = load(
+            "VEH0170",
+            "BodyType,Make,GenModel,Model,AgeAtFirstRegistration,2024Q2,2024Q1\n\
+             Cars,FORD,KA,KA 1.3,New,5,6\n\
+             Cars,FORD,KA,KA 1.3,Over 10 years,0,1\n",
+        );
+        let ages = &stats(&index, "ford_ka_1.3").first_reg_age;
+        let q2 = Quarter::new(2024, 2);
+        assert_eq!(ages["New"].count(q2), Some(5));
+        assert_eq!(ages["Over 10 years"].count(q2), Some(0));
+        assert_eq!(ages["Over 10 years"].count(Quarter::new(2024, 1)), Some(1));
+    }
+
+    #[test]
+    fn veh0150_rejects_columns_that_are_not_quarters() {
+        let tables = tables::registry();
+        let table = tables.iter().find(|t| t.name() == "VEH0150").unwrap();
+        let dir = TempDir::new();
+        let path = dir.join("df_VEH0150.csv");
+        std::fs::write(
+            &path,
+            "BodyType,Make,GenModel,Model,Colour,2024Q2,Total\n\
+             Cars,FORD,KA,KA 1.3,RED,3,3\n",
+        )
+        .unwrap();
+        let err = table.load(&path, &mut Index::new()).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid quarter: \"Total\"");
+    }
+
+    #[test]
+    fn veh0122_latest_quarter_by_local_authority() {
+        let index = load(
+            "VEH0122",
+            "BodyType,Make,GenModel,Model,ONS_Code,ONS_Geography,2024Q2,2024Q1\n\
+             Cars,FORD,KA,KA 1.3,E06000047,County Durham,3,4\n\
+             Cars,FORD,KA,KA 1.3,E09000033,Westminster,0,1\n",
+        );
+        assert_eq!(
+            stats(&index, "ford_ka_1.3").local_authority_licensed,
+            named(&[
+                ("County Durham", Cell::Count(3)),
+                ("Westminster", Cell::Count(0)),
+            ])
+        );
+    }
+
+    #[test]
+    fn streaming_build_matches_normal_build() {
+        let dir = TempDir::new();
+        let inputs = dir.join("csv");
+        write_inputs(&inputs);
+        let normal = dir.join("normal.sqlite3");
+        build(&inputs, &normal, None, false, schema::CURRENT_VERSION);
+        // Spilling every few rows splits each model's stats into several
+        // parts, which go through JSON on their way to the database.
+        let streaming = dir.join("streaming.sqlite3");
+        build(&inputs, &streaming, Some(3), false, schema::CURRENT_VERSION);
+
+        let normal = dump(&normal);
+        assert!(normal.iter().any(|row| row.starts_with("models:")));
+        assert!(normal.iter().any(|row| row.starts_with("rarest_by_make:")));
+        assert_eq!(normal, dump(&streaming));
+    }
+
+    #[test]
+    fn names_with_the_same_slug_merge_into_one_entity() {
+        let dir = TempDir::new();
+        let inputs = dir.join("csv");
+        std::fs::create_dir_all(&inputs).unwrap();
+        std::fs::write(
+            inputs.join("df_VEH0120_UK.csv"),
+            "BodyType,Make,GenModel,Model,Fuel,LicenceStatus,2015Q1\n\
+             Cars,FORD,KA/X,KA X 1.3,PETROL,Licensed,300\n",
+        )
+        .unwrap();
+        std::fs::write(
+            inputs.join("df_VEH0160_UK.csv"),
+            "BodyType,Make,GenModel,Model,Fuel,2015Q1\n\
+             Cars,FORD,KA X,KA X 1.3,PETROL,30\n\
+             Cars,FORD,KA X,KA X 1.6,PETROL,10\n",
+        )
+        .unwrap();
+        let normal = dir.join("normal.sqlite3");
+        let index = build(&inputs, &normal, None, false, schema::CURRENT_VERSION);
+        let generic_model = &index.generic_models["ford_ka_x"];
+        assert_eq!(&*generic_model.name, "KA/X");
+        assert_eq!(
+            index.makes["ford"]
+                .generic_models
+                .iter()
+                .map(|l| (&*l.slug, &*l.name))
+                .collect::<Vec<_>>(),
+            [("ford_ka_x", "KA/X")]
+        );
+        assert_eq!(generic_model.models.len(), 2);
+        for model in index.models.values() {
+            assert_eq!(&*model.generic_model.name, "KA/X");
+        }
+
+        // Loading everything into one index, as a streaming build does.
+        let streaming = dir.join("streaming.sqlite3");
+        build(&inputs, &streaming, Some(1), false, schema::CURRENT_VERSION);
+        assert_eq!(dump(&normal), dump(&streaming));
+    }
+
+    #[test]
+    fn optional_table_that_wont_load_is_skipped() {
+        let dir = TempDir::new();
+        let inputs = dir.join("csv");
+        write_inputs(&inputs);
+        // The second row is bad, so the first must not be merged either.
+        std::fs::write(
+            inputs.join("df_VEH0150.csv"),
+            "BodyType,Make,GenModel,Model,Colour,2015Q1,2014Q4\n\
+             Cars,FORD,KA,KA 1.3,RED,3,0\n\
+             Cars,FORD,FIESTA,FIESTA ZETEC,BLUE,lots,2\n",
+        )
+        .unwrap();
+        let normal = dir.join("normal.sqlite3");
+        let index = build(&inputs, &normal, None, false, schema::CURRENT_VERSION);
+        assert!(index
+            .models
+            .values()
+            .all(|m| m.stats.new_reg_colour.is_empty()));
+        assert!(!index.models["ford_ka_1.3"].stats.new_reg.is_empty());
+        let file = index.inputs.iter().find(|f| f.table == "VEH0150").unwrap();
+        assert_eq!(file.rows, None);
+        assert!(file
+            .skipped
+            .as_ref()
+            .unwrap()
+            .starts_with("df_VEH0150.csv: "));
+
+        let streaming = dir.join("streaming.sqlite3");
+        build(&inputs, &streaming, Some(1), false, schema::CURRENT_VERSION);
+        assert_eq!(dump(&normal), dump(&streaming));
+    }
+
+    #[test]
+    fn required_table_that_wont_load_fails_the_build() {
+        let dir = TempDir::new();
+        let inputs = dir.join("csv");
+        write_inputs(&inputs);
+        std::fs::write(
+            inputs.join("df_VEH0160_UK.csv"),
+            "BodyType,Make,GenModel,Model,Fuel,2015Q1\n\
+             Cars,FORD,KA,KA 1.3,PETROL,lots\n",
+        )
+        .unwrap();
+        let result = parse(&inputs, &dir.join("db.sqlite3"), None, false);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn incremental_build_matches_full_build() {
+        let dir = TempDir::new();
+        let inputs = dir.join("csv");
+        write_inputs(&inputs);
+        let previous = dir.join("previous.sqlite3");
+        build(&inputs, &previous, None, false, schema::CURRENT_VERSION);
+
+        // Changes each table in turn, including the names in it, so that
+        // models move between tables as well as their counts changing.
+        for (filename, contents) in testing::INPUTS {
+            let (header, rows) = contents.split_once('\n').unwrap();
+            let changed = format!("{}\n{}", header, rows.replace('1', "2"));
+            std::fs::write(inputs.join(filename), changed).unwrap();
+
+            // Both carry ids over from the previous build.
+            let full = dir.join("full.sqlite3");
+            std::fs::copy(&previous, &full).unwrap();
+            build(&inputs, &full, None, false, schema::CURRENT_VERSION);
+            let incremental = dir.join("incremental.sqlite3");
+            std::fs::copy(&previous, &incremental).unwrap();
+            build(&inputs, &incremental, None, true, schema::CURRENT_VERSION);
+            assert_eq!(dump(&full), dump(&incremental), "{} changed", filename);
+
+            std::fs::write(inputs.join(filename), contents).unwrap();
+        }
+    }
+
+    #[test]
+    fn makes_and_generic_models_have_their_own_search_indexes() {
+        let dir = TempDir::new();
+        let inputs = dir.join("csv");
+        write_inputs(&inputs);
+        let database = dir.join("db.sqlite3");
+        build(&inputs, &database, None, false, schema::CURRENT_VERSION);
+        let db = Connection::open(&database).unwrap();
+
+        let postings = |table: &str, keyword: &str| -> Vec<u32> {
+            let bytes: Vec<u8> = db
+                .query_row(
+                    &format!("SELECT bytes FROM {table} WHERE keyword = ?1"),
+                    [keyword],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            keywords::decode_postings(&bytes).unwrap()
+        };
+        let id = |table: &str, slug: &str| -> u32 {
+            db.query_row(
+                &format!("SELECT id FROM {table} WHERE slug = ?1"),
+                [slug],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            postings("generic_model_keywords", "ka"),
+            [id("generic_models", "ford_ka")]
+        );
+        assert_eq!(postings("make_keywords", "ford"), [id("makes", "ford")]);
+        assert_eq!(
+            postings("make_keywords", "porsche"),
+            [id("makes", "porsche")]
+        );
+        // Makes are indexed by their own name only.
+        let ka_in_makes: i64 = db
+            .query_row(
+                "SELECT COUNT(1) FROM make_keywords WHERE keyword = 'ka'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(ka_in_makes, 0);
+    }
+
+    #[test]
+    fn version_1_has_the_old_layout() {
+        let dir = TempDir::new();
+        let inputs = dir.join("csv");
+        write_inputs(&inputs);
+        // A name that the current search terms would index differently.
+        let mut vehicles = OpenOptions::new()
+            .append(true)
+            .open(inputs.join("df_VEH0120_UK.csv"))
+            .unwrap();
+        writeln!(
+            vehicles,
+            "Cars,BMW,3 SERIES,320D I AUTO,DIESEL,Licensed,1,2,3"
+        )
+        .unwrap();
+        let database = dir.join("v1.sqlite3");
+        build(&inputs, &database, None, false, 1);
+
+        let db = Connection::open(&database).unwrap();
+        let version: u32 = db
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, 1);
+        let mut stmt = db
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap();
+        let tables: Vec<String> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            tables,
+            [
+                "generic_models",
+                "keywords",
+                "makes",
+                "metaphones",
+                "models"
+            ]
+        );
+
+        let stats = [
+            "quarterly_licensed",
+            "quarterly_sorn",
+            "first_reg_licensed",
+            "first_reg_sorn",
+            "manufacture_licensed",
+            "manufacture_sorn",
+            "new_reg",
+            "petrol_licensed",
+            "petrol_sorn",
+            "diesel_licensed",
+            "diesel_sorn",
+            "other_licensed",
+            "other_sorn",
+        ];
+        let entities = [
+            ("makes", "ford", &["name", "slug", "generic_models"][..]),
+            (
+                "generic_models",
+                "ford_ka",
+                &["name", "slug", "make", "models"][..],
+            ),
+            (
+                "models",
+                "ford_ka_1.3",
+                &["name", "slug", "make", "generic_model"][..],
+            ),
+        ];
+        for (table, slug, fields) in entities {
+            let json: String = db
+                .query_row(
+                    &format!("SELECT json FROM {table} WHERE slug = ?1"),
+                    [slug],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            let keys: Vec<&str> = fields.iter().chain(&stats).copied().collect();
+            let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+            let object = value.as_object().unwrap();
+            assert_eq!(object.len(), keys.len(), "{table}: {json}");
+            let mut from = 0;
+            for key in &keys {
+                // The fields are in the order they always were.
+                let at = json[from..].find(&format!("\"{key}\":")).unwrap();
+                from += at;
+            }
+            for key in stats {
+                for (k, v) in object[key].as_object().unwrap() {
+                    assert!(v.as_i64().is_some_and(|n| n > 0), "{table} {key} {k}: {v}");
+                }
+            }
+        }
+
+        let model: serde_json::Value = serde_json::from_str(
+            &db.query_row(
+                "SELECT json FROM models WHERE slug = 'ford_ka_1.3'",
+                [],
+                |row| row.get::<_, String>(0),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(model["quarterly_licensed"]["2015 q1"], 300);
+        assert_eq!(model["manufacture_licensed"]["1997"], 200);
+        assert!(model["manufacture_sorn"].as_object().unwrap().is_empty());
+
+        let postings: Vec<u8> = db
+            .query_row(
+                "SELECT bytes FROM keywords WHERE keyword = 'ka'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        let id: u32 = db
+            .query_row(
+                "SELECT id FROM models WHERE slug = 'ford_ka_1.3'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(postings, id.to_le_bytes());
+
+        // Every run of letters or digits is a keyword, as it always was, and
+        // nothing else.
+        let mut stmt = db.prepare("SELECT keyword FROM keywords").unwrap();
+        let keywords: HashSet<String> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        for keyword in ["bmw", "320", "d", "i", "auto", "ka", "1", "3"] {
+            assert!(keywords.contains(keyword), "{keyword}");
+        }
+        for keyword in ["320d", "13"] {
+            assert!(!keywords.contains(keyword), "{keyword}");
+        }
+    }
+}
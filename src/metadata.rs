@@ -0,0 +1,170 @@
+use crate::tables::InputFile;
+use crate::{Index, UK_CUTOVER};
+use regex::Regex;
+use rusqlite::Connection;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+
+/// The script that the input files are cleaned up with before a build, as of
+/// this version of the ingester.
+const FIXUP: &str = include_str!("../script/fixup");
+
+/// Writes the metadata table: how the database was built, and from what, so
+/// that any figure in it can be traced back to the input files.
+///
+/// Values are text. `input_files` is a JSON array of each file's table, name,
+/// size, SHA-256, row count and latest year column. `latest_year` is the
+/// latest of those years: the year that the VEH0124 and VEH0220 breakdowns
+/// are for. `fixup_sha256` identifies `script/fixup`, and `name_rewrites` is
+/// a JSON array of the `[from, to]` name rewrites in it, e.g. MERCEDES BENZ
+/// to MERCEDES.
+pub fn save(index: &Index, db: &Connection) -> Result<(), Box<dyn Error>> {
+    db.execute(
+        "INSERT INTO metadata VALUES ('built_at', strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+        [],
+    )?;
+    let mut stmt = db.prepare("INSERT INTO metadata VALUES (?1, ?2)")?;
+    stmt.execute(("crate_version", env!("CARGO_PKG_VERSION")))?;
+    stmt.execute(("uk_cutover", UK_CUTOVER.to_string()))?;
+    stmt.execute((
+        "latest_quarter",
+        index.latest_quarter().map(|q| q.to_string()),
+    ))?;
+    stmt.execute((
+        "latest_year",
+        index.inputs.iter().filter_map(|f| f.latest_year).max(),
+    ))?;
+    stmt.execute(("input_files", serde_json::to_string(&index.inputs)?))?;
+    stmt.execute((
+        "fixup_sha256",
+        format!("{:x}", Sha256::digest(FIXUP.as_bytes())),
+    ))?;
+    stmt.execute((
+        "name_rewrites",
+        serde_json::to_string(&name_rewrites(FIXUP))?,
+    ))?;
+    Ok(())
+}
+
+// The substitutions in a sed script that replace one plain name with another,
+// leaving out the ones that clean up encodings and whitespace.
+fn name_rewrites(script: &str) -> Vec<(&str, &str)> {
+    let rewrite = Regex::new(r"^s/([A-Z0-9 -]+)/([A-Z0-9 -]+)/g$").unwrap();
+    script
+        .lines()
+        .filter_map(|line| rewrite.captures(line))
+        .map(|c| (c.get(1).unwrap().as_str(), c.get(2).unwrap().as_str()))
+        .collect()
+}
+
+/// Reads the input files recorded by a previous build.
+///
+/// Builds from before the metadata table recorded their input files in an
+/// `input_files` table, without sizes or row counts. Those aren't read: the
+/// next build after one of them has to be a full build.
+pub fn input_files(db: &Connection) -> Result<Vec<InputFile>, Box<dyn Error>> {
+    let has_table = |name: &str| -> Result<bool, rusqlite::Error> {
+        db.query_row(
+            "SELECT COUNT(1) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [name],
+            |row| row.get(0),
+        )
+    };
+    if !has_table("metadata")? {
+        return Err(if has_table("input_files")? {
+            "previous database records its input files in the old input_files table; \
+             do a full build first"
+                .into()
+        } else {
+            "previous database doesn't record its input files".into()
+        });
+    }
+    let json: String = db.query_row(
+        "SELECT value FROM metadata WHERE key = 'input_files'",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema;
+    use crate::testing::{build, write_inputs, TempDir};
+
+    #[test]
+    fn reads_input_files() {
+        let db = Connection::open_in_memory().unwrap();
+        schema::create(&db, schema::CURRENT_VERSION).unwrap();
+        db.execute(
+            "INSERT INTO metadata VALUES ('input_files', ?1)",
+            [r#"[{"table":"VEH0160 (UK)","filename":"df_VEH0160_UK.csv","size":12,"sha256":"ab","rows":3}]"#],
+        )
+        .unwrap();
+        let files = input_files(&db).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].key(), ("VEH0160 (UK)", "df_VEH0160_UK.csv", "ab"));
+        assert_eq!(files[0].rows, Some(3));
+    }
+
+    #[test]
+    fn records_the_latest_year_and_name_rewrites() {
+        let dir = TempDir::new();
+        let inputs = dir.join("csv");
+        write_inputs(&inputs);
+        let database = dir.join("db.sqlite3");
+        build(&inputs, &database, None, false, schema::CURRENT_VERSION);
+        let db = Connection::open(&database).unwrap();
+        let value = |key: &str| -> Option<String> {
+            db.query_row("SELECT value FROM metadata WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+        assert_eq!(value("latest_year").as_deref(), Some("2025"));
+        assert_eq!(value("latest_quarter").as_deref(), Some("2015 q1"));
+        assert_eq!(
+            value("fixup_sha256"),
+            Some(format!("{:x}", Sha256::digest(FIXUP.as_bytes())))
+        );
+        let rewrites: Vec<(String, String)> =
+            serde_json::from_str(&value("name_rewrites").unwrap()).unwrap();
+        assert!(rewrites.contains(&("MERCEDES BENZ".to_string(), "MERCEDES".to_string())));
+        let files = input_files(&db).unwrap();
+        let veh0124 = files.iter().find(|f| f.table == "VEH0124").unwrap();
+        assert_eq!(veh0124.latest_year, Some(2025));
+        let veh0160 = files.iter().find(|f| f.table == "VEH0160 (UK)").unwrap();
+        assert_eq!(veh0160.latest_year, None);
+    }
+
+    #[test]
+    fn name_rewrites_from_fixup() {
+        let rewrites = name_rewrites(FIXUP);
+        assert!(rewrites.contains(&("MERCEDES BENZ", "MERCEDES")));
+        assert!(rewrites.contains(&("BORO V6 4MOTION", "BORA V6 4MOTION")));
+        assert!(rewrites.iter().all(|(from, _)| !from.contains('\\')));
+    }
+
+    #[test]
+    fn rejects_the_old_input_files_table() {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE input_files (filename VARCHAR(255) PRIMARY KEY, table_name VARCHAR(255), sha256 VARCHAR(64));",
+        )
+        .unwrap();
+        let err = input_files(&db).unwrap_err();
+        assert!(err.to_string().contains("old input_files table"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_a_database_without_metadata() {
+        let db = Connection::open_in_memory().unwrap();
+        schema::create(&db, 1).unwrap();
+        let err = input_files(&db).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "previous database doesn't record its input files"
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use crate::period::{Quarter, Year};
+use crate::Stats;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Survival and attrition figures derived from the raw counts in `Stats`.
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Metrics {
+    /// Highest licensed count in any quarter.
+    pub licensed_peak: Option<i32>,
+    /// Latest licensed count as a percentage of `licensed_peak`.
+    pub licensed_remaining_pct: Option<f64>,
+    /// Percentage of the licensed count lost since the same quarter in the
+    /// previous year, keyed by quarter.
+    pub licensed_attrition_pct: BTreeMap<Quarter, f64>,
+    /// Vehicles still licensed as a percentage of those registered new, keyed
+    /// by year of manufacture.
+    pub manufacture_survival_pct: BTreeMap<Year, f64>,
+}
+
+// Percentage rounded to one decimal place, to keep the JSON small.
+fn pct(n: i32, d: i32) -> f64 {
+    (n as f64 * 1000.0 / d as f64).round() / 10.0
+}
+
+impl Metrics {
+    pub fn new(stats: &Stats) -> Metrics {
+        let licensed_peak = stats.quarterly_licensed.counts().map(|(_, n)| n).max();
+        let licensed_remaining_pct =
+            match (licensed_peak, stats.quarterly_licensed.counts().next_back()) {
+                (Some(peak), Some((_, latest))) if peak > 0 => Some(pct(latest, peak)),
+                _ => None,
+            };
+
+        let mut licensed_attrition_pct = BTreeMap::new();
+        for (quarter, count) in stats.quarterly_licensed.counts() {
+            let Some(previous) = stats
+                .quarterly_licensed
+                .count(quarter.year_before())
+                .filter(|n| *n > 0)
+            else {
+                continue;
+            };
+            licensed_attrition_pct.insert(quarter, pct(previous - count, previous));
+        }
+
+        let mut new_reg_by_year: BTreeMap<Year, i32> = BTreeMap::new();
+        for (quarter, count) in stats.new_reg.counts() {
+            *new_reg_by_year
+                .entry(Year::Known(quarter.year()))
+                .or_insert(0) += count;
+        }
+        let mut manufacture_survival_pct = BTreeMap::new();
+        for (year, licensed) in &stats.manufacture_licensed {
+            let Some(licensed) = licensed.count() else {
+                continue;
+            };
+            if let Some(registered) = new_reg_by_year.get(year).filter(|n| **n > 0) {
+                manufacture_survival_pct.insert(*year, pct(licensed, *registered));
+            }
+        }
+
+        Metrics {
+            licensed_peak,
+            licensed_remaining_pct,
+            licensed_attrition_pct,
+            manufacture_survival_pct,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::series::Cell;
+
+    fn q(year: u16, quarter: u8) -> Quarter {
+        Quarter::new(year, quarter)
+    }
+
+    #[test]
+    fn empty_stats() {
+        let metrics = Metrics::new(&Stats::new());
+        assert_eq!(metrics.licensed_peak, None);
+        assert_eq!(metrics.licensed_remaining_pct, None);
+        assert!(metrics.licensed_attrition_pct.is_empty());
+        assert!(metrics.manufacture_survival_pct.is_empty());
+    }
+
+    #[test]
+    fn remaining_from_peak() {
+        let mut stats = Stats::new();
+        stats.quarterly_licensed.add(q(2020, 1), Cell::Count(150));
+        stats.quarterly_licensed.add(q(2020, 2), Cell::Count(300));
+        stats.quarterly_licensed.add(q(2020, 3), Cell::Count(100));
+        // A withheld latest quarter falls back to the last known count.
+        stats.quarterly_licensed.add(q(2020, 4), Cell::NotAvailable);
+        let metrics = Metrics::new(&stats);
+        assert_eq!(metrics.licensed_peak, Some(300));
+        assert_eq!(metrics.licensed_remaining_pct, Some(33.3));
+    }
+
+    #[test]
+    fn no_remaining_from_a_zero_peak() {
+        let mut stats = Stats::new();
+        stats.quarterly_licensed.add(q(2020, 1), Cell::Count(0));
+        let metrics = Metrics::new(&stats);
+        assert_eq!(metrics.licensed_peak, Some(0));
+        assert_eq!(metrics.licensed_remaining_pct, None);
+    }
+
+    #[test]
+    fn attrition_against_the_year_before() {
+        let mut stats = Stats::new();
+        stats.quarterly_licensed.add(q(2019, 1), Cell::Count(200));
+        stats.quarterly_licensed.add(q(2019, 2), Cell::Count(0));
+        stats.quarterly_licensed.add(q(2020, 1), Cell::Count(150));
+        stats.quarterly_licensed.add(q(2020, 2), Cell::Count(10));
+        stats.quarterly_licensed.add(q(2020, 3), Cell::Count(10));
+        let metrics = Metrics::new(&stats);
+        // 2020 q2 has a zero base and 2020 q3 has no base at all.
+        assert_eq!(
+            metrics.licensed_attrition_pct,
+            BTreeMap::from([(q(2020, 1), 25.0)])
+        );
+    }
+
+    #[test]
+    fn survival_by_year_of_manufacture() {
+        let mut stats = Stats::new();
+        stats.new_reg.add(q(2015, 1), Cell::Count(100));
+        stats.new_reg.add(q(2015, 3), Cell::Count(300));
+        stats.new_reg.add(q(2016, 1), Cell::Count(0));
+        stats
+            .manufacture_licensed
+            .insert(Year::Known(2015), Cell::Count(100));
+        stats
+            .manufacture_licensed
+            .insert(Year::Known(2016), Cell::Count(5));
+        stats
+            .manufacture_licensed
+            .insert(Year::Known(2017), Cell::Count(5));
+        stats
+            .manufacture_licensed
+            .insert(Year::Unknown, Cell::Count(5));
+        let metrics = Metrics::new(&stats);
+        assert_eq!(
+            metrics.manufacture_survival_pct,
+            BTreeMap::from([(Year::Known(2015), 25.0)])
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use crate::dft;
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+/// A calendar quarter, as used for the columns of the quarterly DfT tables.
+///
+/// Parses from either the CSV column form ("2014Q3") or the serialized form
+/// ("2014 q3"), and orders chronologically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Quarter {
+    year: u16,
+    quarter: u8,
+}
+
+impl Quarter {
+    pub const fn new(year: u16, quarter: u8) -> Quarter {
+        assert!(quarter >= 1 && quarter <= 4);
+        Quarter { year, quarter }
+    }
+
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    /// The same quarter in the previous year.
+    pub fn year_before(&self) -> Quarter {
+        Quarter::new(self.year - 1, self.quarter)
+    }
+
+    /// The quarter after this one.
+    pub fn next(&self) -> Quarter {
+        if self.quarter == 4 {
+            Quarter::new(self.year + 1, 1)
+        } else {
+            Quarter::new(self.year, self.quarter + 1)
+        }
+    }
+
+    /// Fractional year at the end of the quarter, e.g. 2014 q3 -> 2014.75.
+    pub fn end_as_year(&self) -> f64 {
+        self.year as f64 + self.quarter as f64 / 4.0
+    }
+}
+
+impl FromStr for Quarter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Quarter, String> {
+        let (year, quarter) = s
+            .split_once('Q')
+            .or_else(|| s.split_once(" q"))
+            .ok_or_else(|| format!("Invalid quarter: {:?}", s))?;
+        match (year.parse(), quarter.parse()) {
+            (Ok(year), Ok(quarter @ 1..=4)) => Ok(Quarter::new(year, quarter)),
+            _ => Err(format!("Invalid quarter: {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Quarter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} q{}", self.year, self.quarter)
+    }
+}
+
+impl Serialize for Quarter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Quarter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Quarter, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+impl JsonSchema for Quarter {
+    fn schema_name() -> Cow<'static, str> {
+        "Quarter".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "A calendar quarter, e.g. \"2014 q3\".",
+            "type": "string",
+            "pattern": r"^\d{4} q[1-4]$",
+        })
+    }
+}
+
+/// A year of manufacture or first registration. DfT flags the year as not
+/// available for some vehicles, which sort after all known years.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Year {
+    Known(u16),
+    Unknown,
+}
+
+impl From<&dft::OptionalNumber> for Year {
+    fn from(n: &dft::OptionalNumber) -> Year {
+        match n {
+            dft::OptionalNumber::Count(y) => u16::try_from(*y).map_or(Year::Unknown, Year::Known),
+            dft::OptionalNumber::Flag(_) => Year::Unknown,
+        }
+    }
+}
+
+impl FromStr for Year {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Year, String> {
+        match s {
+            "Unknown" => Ok(Year::Unknown),
+            _ => s
+                .parse()
+                .map(Year::Known)
+                .map_err(|_| format!("Invalid year: {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Year {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Year::Known(year) => write!(f, "{}", year),
+            Year::Unknown => write!(f, "Unknown"),
+        }
+    }
+}
+
+impl Serialize for Year {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Year {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Year, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+impl JsonSchema for Year {
+    fn schema_name() -> Cow<'static, str> {
+        "Year".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "A year, e.g. \"1998\", or \"Unknown\".",
+            "type": "string",
+            "pattern": r"^(\d+|Unknown)$",
+        })
+    }
+}
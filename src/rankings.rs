@@ -0,0 +1,269 @@
+use crate::period::Quarter;
+use crate::series::Series;
+use crate::{dft, Index, Model};
+use rusqlite::Connection;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+// Number of models kept in each ranking.
+const TOP_N: usize = 20;
+
+// Smallest licensed count a year ago for a model to rank by decline, so that
+// a handful of cars going off the road doesn't count as a 50% drop.
+const MIN_DECLINE_BASE: i32 = 100;
+
+// Smallest licensed + SORN count for a model to rank by SORN share.
+const MIN_SORN_BASE: i32 = 50;
+
+fn count_at(series: &Series, quarter: Quarter) -> i32 {
+    series.count(quarter).unwrap_or(0)
+}
+
+// Models still licensed in `quarter`, rarest first.
+fn rarest<'a>(models: impl Iterator<Item = &'a Model>, quarter: Quarter) -> Vec<(&'a Model, i32)> {
+    let mut ranked: Vec<(&Model, i32)> = models
+        .map(|m| (m, count_at(&m.stats.quarterly_licensed, quarter)))
+        .filter(|(_, n)| *n > 0)
+        .collect();
+    ranked.sort_by(|(a, n), (b, m)| n.cmp(m).then_with(|| a.slug.cmp(&b.slug)));
+    ranked.truncate(TOP_N);
+    ranked
+}
+
+/// Writes the ranking tables, based on the latest quarter in the index.
+pub fn save(index: &Index, db: &Connection) -> Result<(), Box<dyn Error>> {
+    let Some(latest) = index.latest_quarter() else {
+        return Ok(());
+    };
+
+    {
+        let mut stmt = db.prepare("INSERT INTO rarest_by_make VALUES (?1, ?2, ?3, ?4)")?;
+        for make in index.makes.values() {
+            let models = make
+                .generic_models
+                .iter()
+                .flat_map(|g| index.generic_models[&g.slug].models.iter())
+                .map(|m| &index.models[&m.slug]);
+            for (rank, (model, licensed)) in (1..).zip(rarest(models, latest)) {
+                stmt.execute((&make.slug, rank, &model.slug, licensed))?;
+            }
+        }
+    }
+    {
+        let mut by_body_type: BTreeMap<dft::BodyType, Vec<&Model>> = BTreeMap::new();
+        for model in index.models.values() {
+            by_body_type.entry(model.body_type).or_default().push(model);
+        }
+        let mut stmt = db.prepare("INSERT INTO rarest_by_body_type VALUES (?1, ?2, ?3, ?4)")?;
+        for (body_type, models) in by_body_type {
+            let body_type = serde_json::to_value(body_type)?;
+            for (rank, (model, licensed)) in (1..).zip(rarest(models.into_iter(), latest)) {
+                stmt.execute((body_type.as_str(), rank, &model.slug, licensed))?;
+            }
+        }
+    }
+    {
+        let previous = latest.year_before();
+        let mut declines: Vec<(&Model, i32, i32, f64)> = index
+            .models
+            .values()
+            .map(|m| {
+                let before = count_at(&m.stats.quarterly_licensed, previous);
+                let now = count_at(&m.stats.quarterly_licensed, latest);
+                (m, before, now)
+            })
+            .filter(|(_, before, now)| *before >= MIN_DECLINE_BASE && now < before)
+            .map(|(m, before, now)| {
+                (
+                    m,
+                    before,
+                    now,
+                    (before - now) as f64 * 100.0 / before as f64,
+                )
+            })
+            .collect();
+        declines.sort_by(|a, b| b.3.total_cmp(&a.3).then_with(|| a.0.slug.cmp(&b.0.slug)));
+        let mut stmt = db.prepare("INSERT INTO biggest_declines VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        for (rank, (model, before, now, pct)) in (1..).zip(declines.into_iter().take(TOP_N)) {
+            stmt.execute((rank, &model.slug, before, now, pct))?;
+        }
+    }
+    {
+        let mut shares: Vec<(&Model, i32, i32, f64)> = index
+            .models
+            .values()
+            .map(|m| {
+                let licensed = count_at(&m.stats.quarterly_licensed, latest);
+                let sorn = count_at(&m.stats.quarterly_sorn, latest);
+                (m, licensed, sorn)
+            })
+            .filter(|(_, licensed, sorn)| licensed + sorn >= MIN_SORN_BASE)
+            .map(|(m, licensed, sorn)| {
+                (
+                    m,
+                    licensed,
+                    sorn,
+                    sorn as f64 * 100.0 / (licensed + sorn) as f64,
+                )
+            })
+            .collect();
+        shares.sort_by(|a, b| b.3.total_cmp(&a.3).then_with(|| a.0.slug.cmp(&b.0.slug)));
+        let mut stmt = db.prepare("INSERT INTO highest_sorn_share VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        for (rank, (model, licensed, sorn, pct)) in (1..).zip(shares.into_iter().take(TOP_N)) {
+            stmt.execute((rank, &model.slug, licensed, sorn, pct))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema;
+    use crate::series::Cell;
+
+    const LATEST: Quarter = Quarter::new(2024, 4);
+
+    // Adds a Ford model with its licensed and SORN counts a year before the
+    // latest quarter and in it.
+    fn add(
+        index: &mut Index,
+        model: &str,
+        body_type: dft::BodyType,
+        licensed: (i32, i32),
+        sorn: i32,
+    ) {
+        let slug = index
+            .add(&dft::VehicleIdentity {
+                body_type,
+                make: "FORD",
+                generic_model: "FORD MODEL",
+                model,
+            })
+            .unwrap();
+        let stats = &mut index.models.get_mut(&slug).unwrap().stats;
+        stats
+            .quarterly_licensed
+            .add(LATEST.year_before(), Cell::Count(licensed.0));
+        stats
+            .quarterly_licensed
+            .add(LATEST, Cell::Count(licensed.1));
+        stats.quarterly_sorn.add(LATEST, Cell::Count(sorn));
+    }
+
+    fn rankings(index: &mut Index) -> Connection {
+        index.roll_up();
+        let db = Connection::open_in_memory().unwrap();
+        schema::create(&db, schema::CURRENT_VERSION).unwrap();
+        save(index, &db).unwrap();
+        db
+    }
+
+    fn column<T: rusqlite::types::FromSql>(db: &Connection, sql: &str) -> Vec<T> {
+        let mut stmt = db.prepare(sql).unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn rarest_first_with_ties_by_slug() {
+        let mut index = Index::new();
+        add(&mut index, "KA", dft::BodyType::Cars, (10, 5), 0);
+        add(&mut index, "PUMA", dft::BodyType::Cars, (10, 2), 0);
+        add(&mut index, "CORTINA", dft::BodyType::Cars, (10, 5), 0);
+        add(&mut index, "ANGLIA", dft::BodyType::Cars, (10, 0), 3);
+        add(&mut index, "TRANSIT", dft::BodyType::LightGoods, (10, 1), 0);
+        let db = rankings(&mut index);
+
+        // Anglia has none left, so isn't ranked.
+        assert_eq!(
+            column::<String>(&db, "SELECT model_slug FROM rarest_by_make ORDER BY rank"),
+            ["ford_transit", "ford_puma", "ford_cortina", "ford_ka"]
+        );
+        assert_eq!(
+            column::<i32>(&db, "SELECT rank FROM rarest_by_make ORDER BY rank"),
+            [1, 2, 3, 4]
+        );
+        assert_eq!(
+            column::<String>(
+                &db,
+                "SELECT body_type || ' ' || model_slug FROM rarest_by_body_type \
+                 ORDER BY body_type, rank"
+            ),
+            [
+                "Cars ford_puma",
+                "Cars ford_cortina",
+                "Cars ford_ka",
+                "Light goods vehicles ford_transit",
+            ]
+        );
+    }
+
+    #[test]
+    fn only_the_top_models_are_kept() {
+        let mut index = Index::new();
+        for i in 0..TOP_N + 5 {
+            add(
+                &mut index,
+                &format!("MODEL {i:02}"),
+                dft::BodyType::Cars,
+                (0, i as i32 + 1),
+                0,
+            );
+        }
+        let db = rankings(&mut index);
+        let ranked = column::<String>(&db, "SELECT model_slug FROM rarest_by_make ORDER BY rank");
+        assert_eq!(ranked.len(), TOP_N);
+        assert_eq!(ranked[0], "ford_model_00");
+        assert_eq!(ranked[TOP_N - 1], format!("ford_model_{:02}", TOP_N - 1));
+    }
+
+    #[test]
+    fn biggest_declines_from_a_minimum_base() {
+        let mut index = Index::new();
+        add(&mut index, "KA", dft::BodyType::Cars, (200, 100), 0);
+        add(&mut index, "PUMA", dft::BodyType::Cars, (1000, 100), 0);
+        add(&mut index, "FOCUS", dft::BodyType::Cars, (100, 50), 0);
+        // Growing or flat, so not a decline at all.
+        add(&mut index, "FIESTA", dft::BodyType::Cars, (1000, 1100), 0);
+        add(&mut index, "MONDEO", dft::BodyType::Cars, (500, 500), 0);
+        // Too few a year ago to rank, however steep the drop.
+        add(&mut index, "CORTINA", dft::BodyType::Cars, (99, 1), 0);
+        let db = rankings(&mut index);
+        assert_eq!(
+            column::<String>(&db, "SELECT model_slug FROM biggest_declines ORDER BY rank"),
+            ["ford_puma", "ford_focus", "ford_ka"]
+        );
+        assert_eq!(
+            column::<f64>(
+                &db,
+                "SELECT decline_pct FROM biggest_declines ORDER BY rank"
+            ),
+            [90.0, 50.0, 50.0]
+        );
+    }
+
+    #[test]
+    fn highest_sorn_share_from_a_minimum_base() {
+        let mut index = Index::new();
+        add(&mut index, "KA", dft::BodyType::Cars, (0, 75), 25);
+        add(&mut index, "PUMA", dft::BodyType::Cars, (0, 50), 50);
+        add(&mut index, "FOCUS", dft::BodyType::Cars, (0, 150), 50);
+        // Too few licensed and SORN together to rank.
+        add(&mut index, "CORTINA", dft::BodyType::Cars, (0, 1), 48);
+        let db = rankings(&mut index);
+        assert_eq!(
+            column::<String>(
+                &db,
+                "SELECT model_slug FROM highest_sorn_share ORDER BY rank"
+            ),
+            ["ford_puma", "ford_focus", "ford_ka"]
+        );
+        assert_eq!(
+            column::<f64>(&db, "SELECT sorn_pct FROM highest_sorn_share ORDER BY rank"),
+            [50.0, 25.0, 25.0]
+        );
+    }
+}
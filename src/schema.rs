@@ -0,0 +1,375 @@
+use crate::legacy_json;
+use rusqlite::Connection;
+use std::error::Error;
+
+/// Version of the database layout written by `Index::save`, stored in
+/// `PRAGMA user_version`. Bump it whenever a table or column changes in a
+/// way that a reader would notice, and describe the new layout in `TABLES`.
+pub const CURRENT_VERSION: u32 = 2;
+
+/// Oldest version that can still be written, for readers that haven't
+/// upgraded. Databases from before versioning have a `user_version` of 0.
+pub const OLDEST_VERSION: u32 = 1;
+
+/// What `Index::save` writes for a schema version, beyond the tables that
+/// `describe` lists for it. Every difference between versions is here.
+pub struct Layout {
+    /// Whether makes and generic models have ids, and search indexes of their
+    /// own.
+    pub entity_ids: bool,
+    /// Whether the rankings, id sequences and metadata are written.
+    pub derived_tables: bool,
+    /// Whether posting lists are each id as a little-endian u32, rather than
+    /// versioned and delta-encoded.
+    pub raw_postings: bool,
+    /// Whether models are indexed under every run of letters or digits in
+    /// their full name, rather than by their search terms, which join codes
+    /// like "320d", drop stop words and add synonyms.
+    pub original_keywords: bool,
+    /// Converts an entity's JSON, as `Index` serializes it, for the version.
+    pub entity_json: EntityJson,
+}
+
+/// Converts an entity's JSON, given its kind, e.g. "models".
+pub type EntityJson = fn(&str, String) -> Result<String, Box<dyn Error>>;
+
+/// The layout of `version`, which must be between `OLDEST_VERSION` and
+/// `CURRENT_VERSION`.
+pub fn layout(version: u32) -> Layout {
+    match version {
+        1 => Layout {
+            entity_ids: false,
+            derived_tables: false,
+            raw_postings: true,
+            original_keywords: true,
+            entity_json: |kind, json| legacy_json::entity_json(kind, &json),
+        },
+        _ => Layout {
+            entity_ids: true,
+            derived_tables: true,
+            raw_postings: false,
+            original_keywords: false,
+            entity_json: |_, json| Ok(json),
+        },
+    }
+}
+
+/// A table as it's laid out in a range of schema versions.
+struct Table {
+    name: &'static str,
+    doc: &'static str,
+    /// Name, SQL type and constraints, and description of each column.
+    columns: &'static [(&'static str, &'static str, &'static str)],
+    /// Table constraint, e.g. a primary key over several columns.
+    constraint: Option<&'static str>,
+    /// First and last versions with the table in this form.
+    versions: (u32, u32),
+}
+
+const KEYWORD_COLUMNS: &[(&str, &str, &str)] = &[
+    ("keyword", "VARCHAR(255) PRIMARY KEY", "Search token."),
+    (
+        "bytes",
+        "BLOB",
+        "Posting list of ids with the keyword: a version byte, then the ids as delta-encoded LEB128.",
+    ),
+];
+
+const METAPHONE_COLUMNS: &[(&str, &str, &str)] = &[
+    (
+        "metaphone",
+        "VARCHAR(255) PRIMARY KEY",
+        "Double metaphone code.",
+    ),
+    (
+        "data",
+        "TEXT",
+        "Keywords with the code, separated by \"|\".",
+    ),
+];
+
+static TABLES: &[Table] = &[
+    Table {
+        name: "makes",
+        doc: "Vehicle makes.",
+        columns: &[
+            ("slug", "VARCHAR(255) PRIMARY KEY", "URL slug."),
+            ("name", "VARCHAR(255)", "Display name."),
+            (
+                "json",
+                "TEXT",
+                "The make, its generic models and stats, as JSON.",
+            ),
+        ],
+        constraint: None,
+        versions: (1, 1),
+    },
+    Table {
+        name: "makes",
+        doc: "Vehicle makes.",
+        columns: &[
+            ("slug", "VARCHAR(255) PRIMARY KEY", "URL slug."),
+            ("name", "VARCHAR(255)", "Display name."),
+            (
+                "json",
+                "TEXT",
+                "The make, its generic models and stats, as JSON.",
+            ),
+            (
+                "id",
+                "UNSIGNED INTEGER UNIQUE",
+                "Id in make_keywords, stable between builds.",
+            ),
+        ],
+        constraint: None,
+        versions: (2, CURRENT_VERSION),
+    },
+    Table {
+        name: "generic_models",
+        doc: "Model ranges within a make, e.g. the Ford Fiesta.",
+        columns: &[
+            ("slug", "VARCHAR(255) PRIMARY KEY", "URL slug."),
+            (
+                "json",
+                "TEXT",
+                "The generic model, its models and stats, as JSON.",
+            ),
+        ],
+        constraint: None,
+        versions: (1, 1),
+    },
+    Table {
+        name: "generic_models",
+        doc: "Model ranges within a make, e.g. the Ford Fiesta.",
+        columns: &[
+            ("slug", "VARCHAR(255) PRIMARY KEY", "URL slug."),
+            (
+                "json",
+                "TEXT",
+                "The generic model, its models and stats, as JSON.",
+            ),
+            (
+                "id",
+                "UNSIGNED INTEGER UNIQUE",
+                "Id in generic_model_keywords, stable between builds.",
+            ),
+        ],
+        constraint: None,
+        versions: (2, CURRENT_VERSION),
+    },
+    Table {
+        name: "models",
+        doc: "Individual models, e.g. the Ford Fiesta Zetec 1.25.",
+        columns: &[
+            ("slug", "VARCHAR(255) PRIMARY KEY", "URL slug."),
+            ("id", "UNSIGNED INTEGER UNIQUE", "Id in keywords."),
+            ("json", "TEXT", "The model and its stats, as JSON."),
+        ],
+        constraint: None,
+        versions: (1, CURRENT_VERSION),
+    },
+    Table {
+        name: "id_sequences",
+        doc: "Next id to hand out for each entity table, so that ids are never reused.",
+        columns: &[
+            ("name", "VARCHAR(255) PRIMARY KEY", "Entity table name."),
+            ("next_id", "UNSIGNED INTEGER", "Next unused id."),
+        ],
+        constraint: None,
+        versions: (2, CURRENT_VERSION),
+    },
+    Table {
+        name: "rarest_by_make",
+        doc: "Rarest models still licensed in the latest quarter, per make.",
+        columns: &[
+            ("make_slug", "VARCHAR(255)", "Make."),
+            ("rank", "INTEGER", "Rank from 1, rarest first."),
+            ("model_slug", "VARCHAR(255)", "Model."),
+            (
+                "licensed",
+                "INTEGER",
+                "Licensed count in the latest quarter.",
+            ),
+        ],
+        constraint: Some("PRIMARY KEY (make_slug, rank)"),
+        versions: (2, CURRENT_VERSION),
+    },
+    Table {
+        name: "rarest_by_body_type",
+        doc: "Rarest models still licensed in the latest quarter, per body type.",
+        columns: &[
+            (
+                "body_type",
+                "VARCHAR(255)",
+                "Body type, as in the model JSON.",
+            ),
+            ("rank", "INTEGER", "Rank from 1, rarest first."),
+            ("model_slug", "VARCHAR(255)", "Model."),
+            (
+                "licensed",
+                "INTEGER",
+                "Licensed count in the latest quarter.",
+            ),
+        ],
+        constraint: Some("PRIMARY KEY (body_type, rank)"),
+        versions: (2, CURRENT_VERSION),
+    },
+    Table {
+        name: "biggest_declines",
+        doc: "Models with the biggest drop in licensed count over the last year, leaving out any that held steady or grew.",
+        columns: &[
+            ("rank", "INTEGER PRIMARY KEY", "Rank from 1, biggest first."),
+            ("model_slug", "VARCHAR(255)", "Model."),
+            (
+                "previous_licensed",
+                "INTEGER",
+                "Licensed count a year before the latest quarter.",
+            ),
+            (
+                "licensed",
+                "INTEGER",
+                "Licensed count in the latest quarter.",
+            ),
+            (
+                "decline_pct",
+                "REAL",
+                "Drop as a percentage of previous_licensed.",
+            ),
+        ],
+        constraint: None,
+        versions: (2, CURRENT_VERSION),
+    },
+    Table {
+        name: "highest_sorn_share",
+        doc: "Models with the highest share of SORN vehicles in the latest quarter.",
+        columns: &[
+            ("rank", "INTEGER PRIMARY KEY", "Rank from 1, highest first."),
+            ("model_slug", "VARCHAR(255)", "Model."),
+            (
+                "licensed",
+                "INTEGER",
+                "Licensed count in the latest quarter.",
+            ),
+            ("sorn", "INTEGER", "SORN count in the latest quarter."),
+            (
+                "sorn_pct",
+                "REAL",
+                "SORN as a percentage of licensed plus SORN.",
+            ),
+        ],
+        constraint: None,
+        versions: (2, CURRENT_VERSION),
+    },
+    Table {
+        name: "metadata",
+        doc: "How the database was built: built_at, crate_version, uk_cutover, \
+              latest_quarter, latest_year, fixup_sha256, and input_files and \
+              name_rewrites as JSON arrays.",
+        columns: &[
+            ("key", "VARCHAR(255) PRIMARY KEY", "Name of the value."),
+            ("value", "TEXT", "The value, as text."),
+        ],
+        constraint: None,
+        versions: (2, CURRENT_VERSION),
+    },
+    Table {
+        name: "make_keywords",
+        doc: "Search index of makes, by makes.id.",
+        columns: KEYWORD_COLUMNS,
+        constraint: None,
+        versions: (2, CURRENT_VERSION),
+    },
+    Table {
+        name: "make_metaphones",
+        doc: "Sound-alike lookup for make_keywords.",
+        columns: METAPHONE_COLUMNS,
+        constraint: None,
+        versions: (2, CURRENT_VERSION),
+    },
+    Table {
+        name: "generic_model_keywords",
+        doc: "Search index of generic models, by generic_models.id.",
+        columns: KEYWORD_COLUMNS,
+        constraint: None,
+        versions: (2, CURRENT_VERSION),
+    },
+    Table {
+        name: "generic_model_metaphones",
+        doc: "Sound-alike lookup for generic_model_keywords.",
+        columns: METAPHONE_COLUMNS,
+        constraint: None,
+        versions: (2, CURRENT_VERSION),
+    },
+    Table {
+        name: "keywords",
+        doc: "Search index of models, by models.id.",
+        columns: &[
+            ("keyword", "VARCHAR(255) PRIMARY KEY", "Search token."),
+            (
+                "bytes",
+                "BLOB",
+                "Posting list of ids with the keyword, each a little-endian u32.",
+            ),
+        ],
+        constraint: None,
+        versions: (1, 1),
+    },
+    Table {
+        name: "keywords",
+        doc: "Search index of models, by models.id.",
+        columns: KEYWORD_COLUMNS,
+        constraint: None,
+        versions: (2, CURRENT_VERSION),
+    },
+    Table {
+        name: "metaphones",
+        doc: "Sound-alike lookup for keywords.",
+        columns: METAPHONE_COLUMNS,
+        constraint: None,
+        versions: (1, CURRENT_VERSION),
+    },
+];
+
+/// Parses a schema version given on the command line.
+pub fn parse_version(s: &str) -> Result<u32, String> {
+    match s.parse() {
+        Ok(v) if (OLDEST_VERSION..=CURRENT_VERSION).contains(&v) => Ok(v),
+        _ => Err(format!(
+            "Unsupported schema version: {} (expected {} to {})",
+            s, OLDEST_VERSION, CURRENT_VERSION
+        )),
+    }
+}
+
+/// The tables of `version` as commented SQL, which is both the documentation
+/// of the schema and what `create` runs.
+pub fn describe(version: u32) -> String {
+    let mut sql = format!(
+        "-- howmanyleft database, schema version {version} (PRAGMA user_version = {version}).\n"
+    );
+    for table in TABLES
+        .iter()
+        .filter(|t| (t.versions.0..=t.versions.1).contains(&version))
+    {
+        let mut lines: Vec<String> = table
+            .columns
+            .iter()
+            .map(|(name, sql_type, doc)| format!("    -- {doc}\n    {name} {sql_type}"))
+            .collect();
+        lines.extend(table.constraint.map(|c| format!("    {c}")));
+        sql.push_str(&format!(
+            "\n-- {}\nCREATE TABLE {} (\n{}\n);\n",
+            table.doc,
+            table.name,
+            lines.join(",\n")
+        ));
+    }
+    sql
+}
+
+/// Creates the tables of `version`, and marks the database with it.
+pub fn create(db: &Connection, version: u32) -> Result<(), Box<dyn Error>> {
+    db.execute_batch(&describe(version))?;
+    db.pragma_update(None, "user_version", version)?;
+    Ok(())
+}
@@ -0,0 +1,292 @@
+use crate::dft;
+use crate::period::Quarter;
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+/// A single value in a stats series: either a count (which may be zero) or
+/// one of the DfT flags for a value that was withheld.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cell {
+    Count(i32),
+    /// The sum of the counts in an aggregate that also had values withheld,
+    /// so the true total is at least this.
+    Partial(i32),
+    /// "[x]": not available, usually suppressed to protect confidentiality.
+    NotAvailable,
+    /// "[z]": not applicable.
+    NotApplicable,
+}
+
+impl Cell {
+    /// The count, even if it's only partial.
+    pub fn count(&self) -> Option<i32> {
+        match self {
+            Cell::Count(n) | Cell::Partial(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Adds another row's value into this one. An aggregate is the sum of
+    /// the counts that weren't withheld, marked partial if any were, and only
+    /// an aggregate made up entirely of flags is itself flagged. A value that
+    /// isn't applicable leaves nothing out, so it doesn't make a sum partial.
+    pub fn add(&mut self, other: Cell) {
+        *self = match (*self, other) {
+            (Cell::Count(a), Cell::Count(b)) => Cell::Count(a + b),
+            (Cell::Count(a) | Cell::Partial(a), Cell::Count(b) | Cell::Partial(b)) => {
+                Cell::Partial(a + b)
+            }
+            (Cell::Count(a), Cell::NotApplicable) | (Cell::NotApplicable, Cell::Count(a)) => {
+                Cell::Count(a)
+            }
+            (Cell::Count(a) | Cell::Partial(a), _) | (_, Cell::Count(a) | Cell::Partial(a)) => {
+                Cell::Partial(a)
+            }
+            (Cell::NotAvailable, _) | (_, Cell::NotAvailable) => Cell::NotAvailable,
+            (Cell::NotApplicable, Cell::NotApplicable) => Cell::NotApplicable,
+        }
+    }
+}
+
+impl From<&dft::OptionalNumber> for Cell {
+    fn from(n: &dft::OptionalNumber) -> Cell {
+        match n {
+            dft::OptionalNumber::Count(n) => Cell::Count(*n),
+            dft::OptionalNumber::Flag(dft::FlagType::NotAvailable) => Cell::NotAvailable,
+            dft::OptionalNumber::Flag(dft::FlagType::NotApplicable) => Cell::NotApplicable,
+        }
+    }
+}
+
+// A partial count serializes as `{"partial": n}`, alongside the plain counts
+// and DfT flags.
+#[derive(Serialize, Deserialize)]
+struct PartialCount {
+    partial: i32,
+}
+
+impl Serialize for Cell {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Cell::Count(n) => serializer.serialize_i32(*n),
+            Cell::Partial(n) => PartialCount { partial: *n }.serialize(serializer),
+            Cell::NotAvailable => serializer.serialize_str("[x]"),
+            Cell::NotApplicable => serializer.serialize_str("[z]"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Cell {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Cell, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Number(dft::OptionalNumber),
+            Partial(PartialCount),
+        }
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Number(n) => Cell::from(&n),
+            Repr::Partial(p) => Cell::Partial(p.partial),
+        })
+    }
+}
+
+impl JsonSchema for Cell {
+    fn schema_name() -> Cow<'static, str> {
+        "Cell".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "A count, or \"[x]\" if it's not available, or \"[z]\" if it's not applicable. A total that left out values that weren't available is `{\"partial\": n}`: at least n.",
+            "anyOf": [
+                { "type": "integer", "format": "int32" },
+                { "enum": ["[x]", "[z]"] },
+                {
+                    "type": "object",
+                    "properties": { "partial": { "type": "integer", "format": "int32" } },
+                    "required": ["partial"],
+                    "additionalProperties": false,
+                },
+            ],
+        })
+    }
+}
+
+/// Adds a value into the entry for `key` in a breakdown.
+pub fn add<K: Ord>(breakdown: &mut BTreeMap<K, Cell>, key: K, cell: Cell) {
+    breakdown
+        .entry(key)
+        .and_modify(|c| c.add(cell))
+        .or_insert(cell);
+}
+
+/// Adds a value into the entry for `key` in a breakdown keyed by name,
+/// allocating the key only the first time it's seen.
+pub fn add_named(breakdown: &mut BTreeMap<String, Cell>, key: &str, cell: Cell) {
+    match breakdown.get_mut(key) {
+        Some(c) => c.add(cell),
+        None => {
+            breakdown.insert(key.to_string(), cell);
+        }
+    }
+}
+
+/// Adds every value in `other` into a breakdown.
+pub fn merge<K: Ord + Clone>(breakdown: &mut BTreeMap<K, Cell>, other: &BTreeMap<K, Cell>) {
+    for (key, cell) in other {
+        match breakdown.get_mut(key) {
+            Some(c) => c.add(*cell),
+            None => {
+                breakdown.insert(key.clone(), *cell);
+            }
+        }
+    }
+}
+
+/// The series for `key` in a breakdown of series keyed by name, allocating
+/// the key only the first time it's seen.
+pub fn named_series<'a>(breakdown: &'a mut BTreeMap<String, Series>, key: &str) -> &'a mut Series {
+    if !breakdown.contains_key(key) {
+        breakdown.insert(key.to_string(), Series::default());
+    }
+    breakdown.get_mut(key).unwrap()
+}
+
+/// Adds every series in `other` into a breakdown of series.
+pub fn merge_series<K: Ord + Clone>(
+    breakdown: &mut BTreeMap<K, Series>,
+    other: &BTreeMap<K, Series>,
+) {
+    for (key, series) in other {
+        breakdown.entry(key.clone()).or_default().merge(series);
+    }
+}
+
+/// A quarterly series.
+///
+/// Serializes as a map with every quarter from the first to the last, so that
+/// quarters for which no source table had a column (e.g. the first three
+/// quarters of each year before DfT went quarterly) appear as `null` rather
+/// than being silently skipped.
+#[derive(Debug, Default)]
+pub struct Series(BTreeMap<Quarter, Cell>);
+
+impl Series {
+    pub fn add(&mut self, quarter: Quarter, cell: Cell) {
+        add(&mut self.0, quarter, cell);
+    }
+
+    /// Adds every quarter of another series into this one.
+    pub fn merge(&mut self, other: &Series) {
+        merge(&mut self.0, &other.0);
+    }
+
+    /// Splits the series in two, returning the quarters from `quarter` on.
+    pub fn split_off(&mut self, quarter: Quarter) -> Series {
+        Series(self.0.split_off(&quarter))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn count(&self, quarter: Quarter) -> Option<i32> {
+        self.0.get(&quarter).and_then(Cell::count)
+    }
+
+    /// The quarters with a known count, in order.
+    pub fn counts(&self) -> impl DoubleEndedIterator<Item = (Quarter, i32)> + '_ {
+        self.0.iter().filter_map(|(q, c)| Some((*q, c.count()?)))
+    }
+
+    /// The quarters that had a column in any source table.
+    pub fn quarters(&self) -> impl DoubleEndedIterator<Item = Quarter> + '_ {
+        self.0.keys().copied()
+    }
+}
+
+impl Serialize for Series {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        if let (Some(first), Some(last)) = (self.0.keys().next(), self.0.keys().last()) {
+            let mut quarter = *first;
+            while quarter <= *last {
+                map.serialize_entry(&quarter, &self.0.get(&quarter))?;
+                quarter = quarter.next();
+            }
+        }
+        map.end()
+    }
+}
+
+impl JsonSchema for Series {
+    fn schema_name() -> Cow<'static, str> {
+        "Series".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        let mut schema = BTreeMap::<Quarter, Option<Cell>>::json_schema(generator);
+        schema.insert(
+            "description".to_string(),
+            "Every quarter from the first to the last, with null for a quarter that no table had a column for.".into(),
+        );
+        schema
+    }
+}
+
+impl<'de> Deserialize<'de> for Series {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Series, D::Error> {
+        let cells = BTreeMap::<Quarter, Option<Cell>>::deserialize(deserializer)?;
+        Ok(Series(
+            cells
+                .into_iter()
+                .filter_map(|(q, c)| Some((q, c?)))
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sum(cells: &[Cell]) -> Cell {
+        let mut total = cells[0];
+        for cell in &cells[1..] {
+            total.add(*cell);
+        }
+        total
+    }
+
+    #[test]
+    fn withheld_values_make_a_total_partial() {
+        use Cell::*;
+        assert_eq!(sum(&[Count(2), Count(3)]), Count(5));
+        assert_eq!(sum(&[Count(2), NotAvailable, Count(3)]), Partial(5));
+        assert_eq!(sum(&[NotAvailable, Count(2)]), Partial(2));
+        assert_eq!(sum(&[Partial(2), Count(3)]), Partial(5));
+        assert_eq!(sum(&[Count(3), Partial(2)]), Partial(5));
+        assert_eq!(sum(&[Partial(2), NotApplicable]), Partial(2));
+        assert_eq!(sum(&[Count(2), NotApplicable]), Count(2));
+        assert_eq!(sum(&[NotApplicable, NotAvailable]), NotAvailable);
+        assert_eq!(sum(&[NotApplicable, NotApplicable]), NotApplicable);
+        assert_eq!(Partial(5).count(), Some(5));
+    }
+
+    #[test]
+    fn cells_round_trip_through_json() {
+        let cells = vec![
+            Cell::Count(3),
+            Cell::Partial(4),
+            Cell::NotAvailable,
+            Cell::NotApplicable,
+        ];
+        let json = serde_json::to_string(&cells).unwrap();
+        assert_eq!(json, r#"[3,{"partial":4},"[x]","[z]"]"#);
+        assert_eq!(serde_json::from_str::<Vec<Cell>>(&json).unwrap(), cells);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{forecast, Continuity, Index, Model, Stats};
+use rusqlite::{Connection, Statement};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Rows loaded between spills of the model stats to the staging database,
+/// unless `--spill-rows` says otherwise. Spilling more often keeps less in
+/// memory between spills, at the cost of more, smaller parts to read back
+/// and merge when finishing.
+pub const DEFAULT_SPILL_ROWS: usize = 250_000;
+
+/// Staging database for a streaming build.
+///
+/// While loading, the stats of each model touched since the last spill are
+/// written out as JSON every `spill_rows` rows. Finishing merges the parts
+/// for one entity at a time, and keeps its final JSON here for `save`, so
+/// the index itself only holds names, links and the few quarters that the
+/// rankings need. Memory therefore still grows with the number of entities,
+/// but not with the number of quarters or breakdowns in their stats.
+pub struct Staging {
+    path: PathBuf,
+    db: Connection,
+    spill_rows: usize,
+    rows: usize,
+    touched: BTreeSet<Arc<str>>,
+}
+
+impl Staging {
+    pub fn create(path: &Path, spill_rows: usize) -> Result<Staging, Box<dyn Error>> {
+        let _ = std::fs::remove_file(path);
+        let db = Connection::open(path)?;
+        db.execute_batch(
+            "PRAGMA journal_mode = OFF;
+             PRAGMA synchronous = 0;
+             CREATE TABLE parts (kind VARCHAR(255), slug VARCHAR(255), json TEXT);
+             CREATE INDEX parts_by_slug ON parts (kind, slug);
+             CREATE TABLE finished (kind VARCHAR(255), slug VARCHAR(255), json TEXT, PRIMARY KEY (kind, slug));",
+        )?;
+        Ok(Staging {
+            path: path.to_path_buf(),
+            db,
+            spill_rows,
+            rows: 0,
+            touched: BTreeSet::new(),
+        })
+    }
+
+    /// Records that a row was merged into a model, returning whether it's
+    /// time to spill.
+    pub fn row_loaded(&mut self, model_slug: &Arc<str>) -> bool {
+        if !self.touched.contains(model_slug) {
+            self.touched.insert(model_slug.clone());
+        }
+        self.rows += 1;
+        self.rows.is_multiple_of(self.spill_rows)
+    }
+
+    /// Moves the stats of every model touched since the last spill out of
+    /// the index and into the staging database.
+    pub fn spill(&mut self, models: &mut BTreeMap<Arc<str>, Model>) -> Result<(), Box<dyn Error>> {
+        let tx = self.db.transaction()?;
+        {
+            let mut stmt = tx.prepare("INSERT INTO parts VALUES ('models', ?1, ?2)")?;
+            for slug in std::mem::take(&mut self.touched) {
+                let model = models.get_mut(&slug).unwrap();
+                let stats = std::mem::replace(&mut model.stats, Stats::new());
+                stmt.execute((&slug, serde_json::to_string(&stats)?))?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Does the work of `Index::finalise` for a streaming build. Each
+    /// model's stats are merged from its parts and added as a part of its
+    /// generic model, and each generic model's likewise for its make.
+    pub fn finish(&mut self, index: &mut Index) -> Result<(), Box<dyn Error>> {
+        self.spill(&mut index.models)?;
+        let mut continuity = Continuity::default();
+        let tx = self.db.transaction()?;
+        {
+            let mut parts = tx.prepare("SELECT json FROM parts WHERE kind = ?1 AND slug = ?2")?;
+            let mut add_part = tx.prepare("INSERT INTO parts VALUES (?1, ?2, ?3)")?;
+            let mut finished = tx.prepare("INSERT INTO finished VALUES (?1, ?2, ?3)")?;
+            for model in index.models.values_mut() {
+                model.stats = merge_parts(&mut parts, "models", &model.slug)?;
+                let json = serde_json::to_string(&model.stats)?;
+                add_part.execute(("generic_models", &model.generic_model.slug, json))?;
+                model.stats.compute_metrics();
+                model.extinction = forecast::forecast_stats(&model.stats);
+                finished.execute(("models", &model.slug, serde_json::to_string(&model)?))?;
+                model.stats = for_rankings(&mut model.stats);
+            }
+            for generic_model in index.generic_models.values_mut() {
+                generic_model.stats =
+                    merge_parts(&mut parts, "generic_models", &generic_model.slug)?;
+                let json = serde_json::to_string(&generic_model.stats)?;
+                add_part.execute(("makes", &generic_model.make.slug, json))?;
+                generic_model.stats.compute_metrics();
+                generic_model.extinction = forecast::forecast_stats(&generic_model.stats);
+                let json = serde_json::to_string(&generic_model)?;
+                finished.execute(("generic_models", &generic_model.slug, json))?;
+                generic_model.stats = for_rankings(&mut generic_model.stats);
+            }
+            for make in index.makes.values_mut() {
+                make.stats = merge_parts(&mut parts, "makes", &make.slug)?;
+                continuity.add(&make.stats);
+                make.stats.compute_metrics();
+                finished.execute(("makes", &make.slug, serde_json::to_string(&make)?))?;
+                make.stats = for_rankings(&mut make.stats);
+            }
+        }
+        tx.commit()?;
+        continuity.check();
+        Ok(())
+    }
+
+    /// The JSON for an entity, as it was when its stats were finished.
+    pub fn json(&self, kind: &str, slug: &str) -> Result<String, Box<dyn Error>> {
+        Ok(self.db.query_row(
+            "SELECT json FROM finished WHERE kind = ?1 AND slug = ?2",
+            (kind, slug),
+            |row| row.get(0),
+        )?)
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn merge_parts(parts: &mut Statement, kind: &str, slug: &str) -> Result<Stats, Box<dyn Error>> {
+    let mut stats = Stats::new();
+    for json in parts.query_map((kind, slug), |row| row.get::<_, String>(0))? {
+        stats.merge(&serde_json::from_str(&json?)?);
+    }
+    Ok(stats)
+}
+
+// Keeps only what the rankings look at: the licensed and SORN counts from a
+// year before the latest quarter on.
+fn for_rankings(stats: &mut Stats) -> Stats {
+    let mut kept = Stats::new();
+    let latest = stats
+        .quarterly_licensed
+        .quarters()
+        .next_back()
+        .max(stats.quarterly_sorn.quarters().next_back());
+    if let Some(latest) = latest {
+        kept.quarterly_licensed = stats.quarterly_licensed.split_off(latest.year_before());
+        kept.quarterly_sorn = stats.quarterly_sorn.split_off(latest.year_before());
+    }
+    kept
+}
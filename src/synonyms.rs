@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+
+// Each entry maps a search term that doesn't appear in the DfT data to the
+// tokens that a vehicle's name must all contain for the term to apply.
+static SYNONYMS: &[(&str, &[&str])] = &[
+    // Makes.
+    ("merc", &["mercedes"]),
+    ("benz", &["mercedes"]),
+    ("vw", &["volkswagen"]),
+    ("beemer", &["bmw"]),
+    ("bimmer", &["bmw"]),
+    ("chevy", &["chevrolet"]),
+    // Models.
+    ("landy", &["land", "rover", "defender"]),
+    ("disco", &["land", "rover", "discovery"]),
+    ("rangie", &["range", "rover"]),
+    // Transmissions. DfT usually marks automatics with a literal "AUTO",
+    // which is indexed as it is; these catch the ones named otherwise.
+    ("auto", &["automatic"]),
+    ("auto", &["cvt"]),
+    ("auto", &["dsg"]),
+    ("auto", &["steptronic"]),
+    ("auto", &["tiptronic"]),
+    // Body styles.
+    ("estate", &["avant"]),
+    ("estate", &["kombi"]),
+    ("estate", &["sportwagon"]),
+    ("estate", &["sw"]),
+    ("estate", &["tourer"]),
+    ("estate", &["touring"]),
+    ("estate", &["variant"]),
+    ("convertible", &["cabrio"]),
+    ("convertible", &["cabriolet"]),
+    ("convertible", &["roadster"]),
+    ("convertible", &["spider"]),
+    ("convertible", &["spyder"]),
+    ("convertible", &["volante"]),
+    ("saloon", &["sedan"]),
+    ("hatch", &["hatchback"]),
+];
+
+/// Returns the extra search terms implied by a vehicle's name tokens.
+pub fn expand(tokens: &HashSet<String>) -> HashSet<String> {
+    SYNONYMS
+        .iter()
+        .filter(|(_, required)| required.iter().all(|t| tokens.contains(*t)))
+        .map(|(synonym, _)| synonym.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(words: &[&str]) -> HashSet<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn single_token_synonym() {
+        assert_eq!(expand(&set(&["golf", "variant"])), set(&["estate"]));
+    }
+
+    #[test]
+    fn needs_every_token() {
+        assert_eq!(expand(&set(&["land", "rover"])), set(&[]));
+        assert_eq!(
+            expand(&set(&["land", "rover", "defender", "110"])),
+            set(&["landy"])
+        );
+    }
+
+    #[test]
+    fn several_synonyms() {
+        assert_eq!(
+            expand(&set(&["mercedes", "cabriolet", "tiptronic"])),
+            set(&["merc", "benz", "convertible", "auto"])
+        );
+    }
+
+    #[test]
+    fn literal_auto_is_indexed() {
+        assert_eq!(
+            crate::search_terms("FORD FIESTA ZETEC AUTO"),
+            set(&["ford", "fiesta", "zetec", "auto"])
+        );
+        assert!(crate::search_terms("FORD FIESTA ZETEC AUTOMATIC").contains("auto"));
+    }
+
+    #[test]
+    fn no_synonyms() {
+        assert_eq!(expand(&set(&["fiesta", "zetec"])), set(&[]));
+    }
+}
@@ -0,0 +1,435 @@
+use crate::{dft, Index, Merge, Stats};
+use csv::Reader;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+/// A DfT table that the ingester knows how to load. Tables are shared
+/// between the loader threads.
+pub trait Table: Sync {
+    /// Name of the table for the build listing.
+    fn name(&self) -> &str;
+
+    /// The DfT table that this is loaded from, e.g. "VEH0120" for both the
+    /// GB and UK parts of VEH0120. Tables from the same source merge into
+    /// the same stats fields.
+    fn source(&self) -> &str {
+        self.name().split(' ').next().unwrap()
+    }
+
+    /// Whether a file in the input directory belongs to this table.
+    fn matches(&self, filename: &str) -> bool;
+
+    /// Merges every row of the file into the index, returning the row count.
+    fn load(&self, path: &Path, index: &mut Index) -> Result<usize, Box<dyn Error>>;
+
+    /// Clears the stats fields that this table's source merges into.
+    fn clear(&self, stats: &mut Stats);
+
+    /// Whether a file that won't load fails the build, rather than being
+    /// skipped with a warning.
+    fn required(&self) -> bool;
+}
+
+/// A table of CSV rows of type `R`, each merged into `Stats` by `merge`.
+/// `clear` empties the fields that `merge` adds to.
+pub struct CsvTable<R> {
+    name: &'static str,
+    pattern: Regex,
+    merge: Merge<R>,
+    clear: fn(&mut Stats),
+    required: bool,
+    row: PhantomData<fn() -> R>,
+}
+
+impl<R> CsvTable<R> {
+    pub fn new(
+        name: &'static str,
+        pattern: &str,
+        merge: Merge<R>,
+        clear: fn(&mut Stats),
+    ) -> CsvTable<R> {
+        CsvTable {
+            name,
+            pattern: Regex::new(pattern).unwrap(),
+            merge,
+            clear,
+            required: true,
+            row: PhantomData,
+        }
+    }
+
+    /// Skips a file of this table that won't load, with a warning, instead
+    /// of failing the build.
+    pub fn optional(mut self) -> CsvTable<R> {
+        self.required = false;
+        self
+    }
+}
+
+impl<R> Table for CsvTable<R>
+where
+    R: DeserializeOwned + dft::HasIdentity,
+{
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn matches(&self, filename: &str) -> bool {
+        self.pattern.is_match(filename)
+    }
+
+    fn load(&self, path: &Path, index: &mut Index) -> Result<usize, Box<dyn Error>> {
+        let reader = Reader::from_reader(File::open(path)?);
+        let mut rows = 0;
+        for result in reader.into_deserialize() {
+            index.insert(result?, self.merge)?;
+            rows += 1;
+        }
+        Ok(rows)
+    }
+
+    fn clear(&self, stats: &mut Stats) {
+        (self.clear)(stats);
+    }
+
+    fn required(&self) -> bool {
+        self.required
+    }
+}
+
+/// Every table the ingester supports, in the order they're loaded.
+///
+/// The column names of VEH0121, VEH0122, VEH0125, VEH0150 and VEH0170 haven't
+/// been checked against the published files yet, nor have the keepership
+/// values in VEH0125, so those tables are optional.
+pub fn registry() -> Vec<Box<dyn Table>> {
+    vec![
+        Box::new(CsvTable::<dft::Veh0120>::new(
+            "VEH0120 (GB)",
+            r"^df_VEH0120_GB\.csv$",
+            Stats::merge_veh0120_gb,
+            Stats::clear_veh0120,
+        )),
+        Box::new(CsvTable::<dft::Veh0120>::new(
+            "VEH0120 (UK)",
+            r"^df_VEH0120_UK\.csv$",
+            Stats::merge_veh0120_uk,
+            Stats::clear_veh0120,
+        )),
+        Box::new(
+            CsvTable::<dft::Veh0121>::new(
+                "VEH0121",
+                r"^df_VEH0121\.csv$",
+                Stats::merge_veh0121,
+                Stats::clear_veh0121,
+            )
+            .optional(),
+        ),
+        Box::new(
+            CsvTable::<dft::Veh0122>::new(
+                "VEH0122",
+                r"^df_VEH0122\.csv$",
+                Stats::merge_veh0122,
+                Stats::clear_veh0122,
+            )
+            .optional(),
+        ),
+        Box::new(CsvTable::<dft::Veh0124>::new(
+            "VEH0124",
+            r"^df_VEH0124_[A-Z]+\.csv$",
+            Stats::merge_veh0124,
+            Stats::clear_veh0124,
+        )),
+        Box::new(
+            CsvTable::<dft::Veh0125>::new(
+                "VEH0125",
+                r"^df_VEH0125\.csv$",
+                Stats::merge_veh0125,
+                Stats::clear_veh0125,
+            )
+            .optional(),
+        ),
+        Box::new(
+            CsvTable::<dft::Veh0150>::new(
+                "VEH0150",
+                r"^df_VEH0150\.csv$",
+                Stats::merge_veh0150,
+                Stats::clear_veh0150,
+            )
+            .optional(),
+        ),
+        Box::new(CsvTable::<dft::Veh0160>::new(
+            "VEH0160 (GB)",
+            r"^df_VEH0160_GB\.csv$",
+            Stats::merge_veh0160_gb,
+            Stats::clear_veh0160,
+        )),
+        Box::new(CsvTable::<dft::Veh0160>::new(
+            "VEH0160 (UK)",
+            r"^df_VEH0160_UK\.csv$",
+            Stats::merge_veh0160_uk,
+            Stats::clear_veh0160,
+        )),
+        Box::new(
+            CsvTable::<dft::Veh0170>::new(
+                "VEH0170",
+                r"^df_VEH0170\.csv$",
+                Stats::merge_veh0170,
+                Stats::clear_veh0170,
+            )
+            .optional(),
+        ),
+        Box::new(CsvTable::<dft::Veh0220>::new(
+            "VEH0220",
+            r"^df_VEH0220\.csv$",
+            Stats::merge_veh0220,
+            Stats::clear_veh0220,
+        )),
+    ]
+}
+
+/// A file in the input directory, as recorded in the database's metadata,
+/// so that an incremental build can tell which tables have changed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputFile {
+    pub table: String,
+    pub filename: String,
+    /// Size in bytes.
+    pub size: u64,
+    /// SHA-256 of the file's contents, in hex.
+    pub sha256: String,
+    /// Rows loaded from the file, once it's been loaded.
+    pub rows: Option<usize>,
+    /// Why the file was skipped, if it was from an optional table and
+    /// wouldn't load.
+    #[serde(default)]
+    pub skipped: Option<String>,
+    /// The latest year with a column of its own in the file's header, for
+    /// the tables with yearly columns, e.g. VEH0124.
+    #[serde(default)]
+    pub latest_year: Option<u16>,
+}
+
+impl InputFile {
+    /// What identifies the file and its contents: its table, name and hash.
+    pub fn key(&self) -> (&str, &str, &str) {
+        (&self.table, &self.filename, &self.sha256)
+    }
+
+    /// Whether `other` is the same file of the same table, unchanged.
+    pub fn same_as(&self, other: &InputFile) -> bool {
+        self.key() == other.key()
+    }
+}
+
+/// A file to load, and the table it belongs to.
+pub struct Input<'a> {
+    pub table: &'a dyn Table,
+    pub path: PathBuf,
+    pub file: InputFile,
+}
+
+// A loaded partial index and its row count, or the error loading it.
+type Loaded = Result<(Index, usize), String>;
+
+// Loads each input into its own partial index, spread across one thread per
+// CPU, and passes each to `loaded` in input order, as soon as it and every
+// input before it have finished, so that partials are merged and freed while
+// later files are still loading. Stops at the first error from `loaded`.
+fn load_all(
+    inputs: &[&Input],
+    mut loaded: impl FnMut(usize, Loaded) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let next = AtomicUsize::new(0);
+    let threads = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(inputs.len());
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let next = &next;
+            scope.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(input) = inputs.get(i) else {
+                    break;
+                };
+                let mut index = Index::new();
+                let result = input
+                    .table
+                    .load(&input.path, &mut index)
+                    .map(|rows| (index, rows))
+                    .map_err(|e| format!("{}: {}", input.file.filename, e));
+                if sender.send((i, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        // Results that finished before an earlier input's.
+        let mut waiting = BTreeMap::new();
+        for i in 0..inputs.len() {
+            let result = loop {
+                if let Some(result) = waiting.remove(&i) {
+                    break result;
+                }
+                let (j, result) = receiver.recv().unwrap();
+                waiting.insert(j, result);
+            };
+            if let Err(err) = loaded(i, result) {
+                // Stop the workers taking any more inputs.
+                next.store(inputs.len(), Ordering::Relaxed);
+                return Err(err);
+            }
+        }
+        Ok(())
+    })
+}
+
+fn sha256(path: &Path) -> Result<String, std::io::Error> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Parses a year column's name, e.g. "2025".
+pub fn parse_year(s: &str) -> Result<u16, String> {
+    s.parse().map_err(|_| format!("Invalid year: {:?}", s))
+}
+
+// The latest year column in a CSV file's header.
+fn latest_year(path: &Path) -> Result<Option<u16>, csv::Error> {
+    let mut reader = Reader::from_path(path)?;
+    Ok(reader
+        .headers()?
+        .iter()
+        .filter_map(|column| parse_year(column).ok())
+        .max())
+}
+
+/// Finds and hashes every file in `dir` that belongs to one of `tables`, in
+/// table order then filename order, and lists what was skipped and missing.
+pub fn find<'a>(
+    dir: &Path,
+    tables: &'a [Box<dyn Table>],
+) -> Result<Vec<Input<'a>>, Box<dyn Error>> {
+    let mut files: Vec<(String, PathBuf)> = std::fs::read_dir(dir)?
+        .map(|entry| {
+            let entry = entry?;
+            Ok((
+                entry.file_name().to_string_lossy().into_owned(),
+                entry.path(),
+            ))
+        })
+        .collect::<Result<_, std::io::Error>>()?;
+    files.sort();
+
+    let mut inputs = Vec::new();
+    for table in tables {
+        let before = inputs.len();
+        for (filename, path) in files.iter().filter(|(f, _)| table.matches(f)) {
+            inputs.push(Input {
+                table: table.as_ref(),
+                file: InputFile {
+                    table: table.name().to_string(),
+                    filename: filename.clone(),
+                    size: path.metadata()?.len(),
+                    sha256: sha256(path)?,
+                    rows: None,
+                    skipped: None,
+                    latest_year: latest_year(path)?,
+                },
+                path: path.clone(),
+            });
+        }
+        if inputs.len() == before {
+            println!("Not found: {}", table.name());
+        }
+    }
+    for (filename, _) in &files {
+        if !tables.iter().any(|t| t.matches(filename)) {
+            println!("Skipped: {}", filename);
+        }
+    }
+    Ok(inputs)
+}
+
+/// Loads `inputs` into the index, lists what was loaded, and returns the row
+/// count of each input, or why it was skipped.
+///
+/// Files are parsed concurrently, and the partial indexes merged in the
+/// order given, so the output doesn't depend on scheduling. Streaming builds
+/// parse one file at a time instead.
+///
+/// A file of an optional table that won't load is skipped with a warning,
+/// and nothing from it is merged. A streaming build parses such a file on its
+/// own first, as it would otherwise merge the rows before the bad one.
+pub fn load(
+    inputs: &[&Input],
+    index: &mut Index,
+) -> Result<Vec<Result<usize, String>>, Box<dyn Error>> {
+    let mut counts = Vec::with_capacity(inputs.len());
+    // A streaming build loads straight into the index, one file at a time, so
+    // that it can spill stats as they grow.
+    if index.staging.is_some() {
+        for input in inputs {
+            let checked = if input.table.required() {
+                Ok(())
+            } else {
+                input
+                    .table
+                    .load(&input.path, &mut Index::new())
+                    .map(|_| ())
+                    .map_err(|e| format!("{}: {}", input.file.filename, e))
+            };
+            let loaded = match checked {
+                Ok(()) => Ok(input.table.load(&input.path, index)?),
+                Err(err) => Err(err),
+            };
+            record(input, loaded, &mut counts)?;
+        }
+    } else {
+        load_all(inputs, |i, result| {
+            let loaded = result.map(|(partial, rows)| {
+                index.merge(partial);
+                rows
+            });
+            record(inputs[i], loaded, &mut counts)
+        })?;
+    }
+    Ok(counts)
+}
+
+// Lists an input as loaded or skipped, and adds its result to `counts`. A
+// required table that won't load fails the build.
+fn record(
+    input: &Input,
+    loaded: Result<usize, String>,
+    counts: &mut Vec<Result<usize, String>>,
+) -> Result<(), Box<dyn Error>> {
+    match &loaded {
+        Ok(rows) => println!(
+            "Loaded {}: {} ({} rows)",
+            input.table.name(),
+            input.file.filename,
+            rows
+        ),
+        Err(err) if !input.table.required() => {
+            println!("Warning: skipped {}: {}", input.table.name(), err)
+        }
+        Err(err) => return Err(err.clone().into()),
+    }
+    counts.push(loaded);
+    Ok(())
+}
@@ -0,0 +1,184 @@
+use crate::staging::Staging;
+use crate::{tables, Index};
+use rusqlite::types::Value;
+use rusqlite::Connection;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A directory for a test's files, removed when it's dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "ingest-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn join(&self, path: &str) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Loads CSV rows through the registered table called `table`.
+pub fn load(table: &str, csv: &str) -> Index {
+    let tables = tables::registry();
+    let table = tables.iter().find(|t| t.name() == table).unwrap();
+    let dir = TempDir::new();
+    let path = dir.join("table.csv");
+    fs::write(&path, csv).unwrap();
+    let mut index = Index::new();
+    table.load(&path, &mut index).unwrap();
+    index
+}
+
+/// A small copy of every input table, with a few makes and models.
+pub static INPUTS: &[(&str, &str)] = &[
+    (
+        "df_VEH0120_GB.csv",
+        "BodyType,Make,GenModel,Model,Fuel,LicenceStatus,2014Q2,2014Q1,2013Q4\n\
+         Cars,FORD,KA,KA 1.3,PETROL,Licensed,410,420,430\n\
+         Cars,FORD,KA,KA 1.3,PETROL,SORN,20,21,22\n\
+         Cars,FORD,FIESTA,FIESTA ZETEC,PETROL,Licensed,900,910,920\n\
+         Cars,FORD,FIESTA,FIESTA ZETEC,DIESEL,Licensed,100,110,120\n\
+         Cars,PORSCHE,911,911,PETROL,Licensed,50,[x],52\n",
+    ),
+    (
+        "df_VEH0120_UK.csv",
+        "BodyType,Make,GenModel,Model,Fuel,LicenceStatus,2015Q1,2014Q4,2014Q3\n\
+         Cars,FORD,KA,KA 1.3,PETROL,Licensed,300,350,400\n\
+         Cars,FORD,KA,KA 1.3,PETROL,SORN,60,40,20\n\
+         Cars,FORD,FIESTA,FIESTA ZETEC,PETROL,Licensed,880,885,890\n\
+         Cars,FORD,FIESTA,FIESTA ZETEC,DIESEL,Licensed,90,95,99\n\
+         Cars,PORSCHE,911,911,PETROL,Licensed,49,49,50\n\
+         Light goods vehicles,FORD,TRANSIT,TRANSIT 350,DIESEL,Licensed,5,6,7\n",
+    ),
+    (
+        "df_VEH0121.csv",
+        "BodyType,Make,GenModel,Model,ONS_Code,ONS_Geography,2015Q1,2014Q4\n\
+         Cars,FORD,KA,KA 1.3,E12000001,North East,100,110\n\
+         Cars,FORD,KA,KA 1.3,W92000004,Wales,[x],5\n\
+         Cars,PORSCHE,911,911,E12000001,North East,0,1\n",
+    ),
+    (
+        "df_VEH0122.csv",
+        "BodyType,Make,GenModel,Model,ONS_Code,ONS_Geography,2015Q1,2014Q4\n\
+         Cars,FORD,KA,KA 1.3,E06000047,County Durham,30,31\n\
+         Cars,PORSCHE,911,911,E09000033,Westminster,12,12\n",
+    ),
+    (
+        "df_VEH0124_AM.csv",
+        "BodyType,Make,GenModel,Model,YearFirstUsed,YearManufacture,LicenceStatus,2025,2024\n\
+         Cars,FORD,KA,KA 1.3,1998,1997,Licensed,200,210\n\
+         Cars,FORD,KA,KA 1.3,1998,1997,SORN,[x],1\n\
+         Cars,FORD,CORTINA,CORTINA 1600,1970,1970,Licensed,0,0\n",
+    ),
+    (
+        "df_VEH0124_NZ.csv",
+        "BodyType,Make,GenModel,Model,YearFirstUsed,YearManufacture,LicenceStatus,2025,2024\n\
+         Cars,PORSCHE,911,911,1989,[x],Licensed,40,41\n",
+    ),
+    (
+        "df_VEH0125.csv",
+        "BodyType,Make,GenModel,Model,Keepership,2015Q1,2014Q4\n\
+         Cars,FORD,KA,KA 1.3,Private,280,330\n\
+         Cars,FORD,KA,KA 1.3,Company,20,20\n",
+    ),
+    (
+        "df_VEH0150.csv",
+        "BodyType,Make,GenModel,Model,Colour,2015Q1,2014Q4\n\
+         Cars,FORD,KA,KA 1.3,RED,3,0\n\
+         Cars,FORD,FIESTA,FIESTA ZETEC,BLUE,[x],2\n",
+    ),
+    (
+        "df_VEH0160_GB.csv",
+        "BodyType,Make,GenModel,Model,Fuel,2014Q2,2014Q1\n\
+         Cars,FORD,FIESTA,FIESTA ZETEC,PETROL,40,45\n",
+    ),
+    (
+        "df_VEH0160_UK.csv",
+        "BodyType,Make,GenModel,Model,Fuel,2015Q1,2014Q4,2014Q3\n\
+         Cars,FORD,FIESTA,FIESTA ZETEC,PETROL,30,35,41\n\
+         Cars,FORD,KA,KA 1.3,PETROL,0,0,1\n",
+    ),
+    (
+        "df_VEH0170.csv",
+        "BodyType,Make,GenModel,Model,AgeAtFirstRegistration,2015Q1,2014Q4\n\
+         Cars,FORD,FIESTA,FIESTA ZETEC,New,30,35\n\
+         Cars,PORSCHE,911,911,Over 10 years,1,0\n",
+    ),
+    (
+        "df_VEH0220.csv",
+        "BodyType,Make,GenModel,Model,Fuel,EngineSizeSimple,EngineSizeDesc,LicenceStatus,2025,2024\n\
+         Cars,FORD,KA,KA 1.3,PETROL,1,1001cc to 1500cc,Licensed,200,210\n\
+         Cars,PORSCHE,911,911,PETROL,3,Over 3000cc,Licensed,40,41\n",
+    ),
+];
+
+/// Writes `INPUTS` to `dir`.
+pub fn write_inputs(dir: &Path) {
+    fs::create_dir_all(dir).unwrap();
+    for (filename, contents) in INPUTS {
+        fs::write(dir.join(filename), contents).unwrap();
+    }
+}
+
+/// Builds `database` from the tables in `input_dir`, as `main` does. A
+/// streaming build spills every `spill_rows` rows.
+pub fn build(
+    input_dir: &Path,
+    database: &Path,
+    spill_rows: Option<usize>,
+    incremental: bool,
+    version: u32,
+) -> Index {
+    let staging = spill_rows.map(|rows| {
+        let mut path = database.as_os_str().to_owned();
+        path.push(".staging");
+        Staging::create(Path::new(&path), rows).unwrap()
+    });
+    let mut index = crate::parse(input_dir, database, staging, incremental).unwrap();
+    index.finalise().unwrap();
+    index.save(database, version).unwrap();
+    index
+}
+
+/// Every row of every table in a database, in table order then row order,
+/// apart from the build time.
+pub fn dump(database: &Path) -> Vec<String> {
+    let db = Connection::open(database).unwrap();
+    let tables: Vec<String> = {
+        let mut stmt = db
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap();
+        let names = stmt.query_map([], |row| row.get(0)).unwrap();
+        names.map(Result::unwrap).collect()
+    };
+    let mut rows = Vec::new();
+    for table in tables {
+        let mut stmt = db.prepare(&format!("SELECT * FROM {table}")).unwrap();
+        let columns = stmt.column_count();
+        let mut query = stmt.query([]).unwrap();
+        while let Some(row) = query.next().unwrap() {
+            let values: Vec<String> = (0..columns)
+                .map(|i| format!("{:?}", row.get::<_, Value>(i).unwrap()))
+                .collect();
+            rows.push(format!("{}: {}", table, values.join(", ")));
+        }
+    }
+    rows.retain(|row| !row.starts_with("metadata: Text(\"built_at\")"));
+    rows
+}